@@ -0,0 +1,119 @@
+use crate::{
+    error::RuntimeError,
+    function::Function,
+    gc::{self, Trace, Tracer},
+    literal::Literal,
+};
+use std::{cell::RefCell, collections::HashMap, io::Write, rc::Rc};
+
+#[derive(Debug)]
+pub struct Class {
+    name: String,
+    superclass: Option<Rc<Class>>,
+    methods: HashMap<String, Rc<Function>>,
+}
+
+impl Class {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<Class>>,
+        methods: HashMap<String, Rc<Function>>,
+    ) -> Self {
+        Self {
+            name,
+            superclass,
+            methods,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Looks in this class first and then up the superclass chain
+    pub fn find_method(&self, name: &str) -> Option<Rc<Function>> {
+        self.methods.get(name).cloned().or_else(|| {
+            self.superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name))
+        })
+    }
+
+    /// Calling a class takes the same arguments as its `init` method
+    pub fn arity(&self) -> usize {
+        self.find_method("init").map_or(0, |init| init.arity())
+    }
+
+    pub fn instantiate<W: Write>(
+        self: &Rc<Self>,
+        arguments: Vec<Literal>,
+        output: &mut W,
+    ) -> Result<Literal, RuntimeError> {
+        let instance = Literal::Instance(gc::manage(Instance::new(self.clone())));
+        if let Some(init) = self.find_method("init") {
+            init.bind(instance.clone()).call(arguments, output)?;
+        }
+        Ok(instance)
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    class: Rc<Class>,
+    fields: RefCell<HashMap<String, Literal>>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn class(&self) -> &Class {
+        &self.class
+    }
+
+    /// Fields shadow methods, methods come back bound to this instance
+    pub fn get(self: &Rc<Self>, name: &str) -> Option<Literal> {
+        if let Some(value) = self.fields.borrow().get(name) {
+            return Some(value.clone());
+        }
+        let method = self.class.find_method(name)?;
+        Some(Literal::Function(gc::manage(
+            method.bind(Literal::Instance(self.clone())),
+        )))
+    }
+
+    pub fn set(&self, name: String, value: Literal) {
+        self.fields.borrow_mut().insert(name, value);
+    }
+}
+
+impl Trace for Class {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(superclass) = &self.superclass {
+            tracer.object(superclass);
+        }
+        self.methods
+            .values()
+            .for_each(|method| tracer.object(method));
+    }
+}
+
+impl Trace for Instance {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.object(&self.class);
+        let Ok(fields) = self.fields.try_borrow() else {
+            return tracer.busy();
+        };
+        fields.values().for_each(|value| tracer.literal(value));
+    }
+
+    fn clear(&self) {
+        if let Ok(mut fields) = self.fields.try_borrow_mut() {
+            fields.clear();
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{error::RuntimeError, parser::ParseError, span::Span};
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// An error ready to be shown to the user, rendered like a compiler error:
+///
+/// ```text
+/// error[E0002]: Unterminated string.
+///  --> main.lox:1:7
+///   |
+/// 1 | print "hello;
+///   |       ^~~~~~~
+///   = help: add a closing '"'
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            span,
+            notes: vec![],
+            help: None,
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Renders the diagnostic against the source it was raised for, `name` is
+    /// the file the source came from. Spans covering several lines are only
+    /// underlined up to the end of their first line. Without any source, as
+    /// for precompiled bytecode, only the location is shown.
+    pub fn render(&self, name: &str, source: &str, colour: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if colour {
+                format!("{style}{text}{RESET}")
+            } else {
+                text.to_string()
+            }
+        };
+
+        let Span { line, column, .. } = self.span;
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+        let text = source[line_start..]
+            .split('\n')
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches('\r');
+        let offset = start - line_start;
+        let underlined = text
+            .get(offset..self.span.end.max(start) - line_start)
+            .or_else(|| text.get(offset..))
+            .unwrap_or_default();
+        // keep tabs so the carets line up with the source line above them
+        let padding: String = text[..offset.min(text.len())]
+            .chars()
+            .map(|character| if character == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underline = format!(
+            "^{}",
+            "~".repeat(underlined.chars().count().saturating_sub(1))
+        );
+
+        let gutter = " ".repeat(line.to_string().len());
+        let mut rendered = format!(
+            "{}{}\n{gutter}{} {name}:{line}:{column}\n",
+            paint(RED, &format!("error[{}]", self.code)),
+            paint(BOLD, &format!(": {}", self.message)),
+            paint(BLUE, "-->"),
+        );
+        if !source.is_empty() {
+            rendered += &format!("{gutter} {}\n", paint(BLUE, "|"));
+            rendered += &format!("{} {text}\n", paint(BLUE, &format!("{line} |")));
+            rendered += &format!(
+                "{gutter} {} {padding}{}\n",
+                paint(BLUE, "|"),
+                paint(RED, &underline)
+            );
+        }
+        for note in &self.notes {
+            rendered += &format!("{gutter} {} {note}\n", paint(BLUE, "= note:"));
+        }
+        if let Some(help) = &self.help {
+            rendered += &format!("{gutter} {} {help}\n", paint(BLUE, "= help:"));
+        }
+        rendered
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(error: ParseError) -> Self {
+        Self {
+            code: error.code,
+            message: error.message,
+            span: error.span,
+            notes: vec![],
+            help: error.help,
+        }
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        Self {
+            code: error.code,
+            message: error.message.clone(),
+            span: error.span,
+            notes: vec![],
+            help: error.help.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Diagnostic;
+    use crate::span::Span;
+
+    #[test]
+    fn underlines_the_span() {
+        let source = "var a = 1;\nprint a + \"two\";\n";
+        let diagnostic = Diagnostic::new(
+            "E0210",
+            "Operands must be two numbers or two strings.",
+            Span {
+                start: 17,
+                end: 26,
+                line: 2,
+                column: 7,
+            },
+        )
+        .with_note("the left operand is a number")
+        .with_help("convert one side with str() or num()");
+
+        assert_eq!(
+            diagnostic.render("main.lox", source, false),
+            "error[E0210]: Operands must be two numbers or two strings.
+ --> main.lox:2:7
+  |
+2 | print a + \"two\";
+  |       ^~~~~~~~~
+  = note: the left operand is a number
+  = help: convert one side with str() or num()
+"
+        );
+    }
+
+    #[test]
+    fn multi_line_spans_stop_at_the_end_of_the_first_line() {
+        let source = "\tprint \"abc\ndef";
+        let diagnostic = Diagnostic::new(
+            "E0002",
+            "Unterminated string.",
+            Span {
+                start: 7,
+                end: 15,
+                line: 1,
+                column: 8,
+            },
+        );
+
+        assert_eq!(
+            diagnostic.render("main.lox", source, false),
+            "error[E0002]: Unterminated string.
+ --> main.lox:1:8
+  |
+1 | \tprint \"abc
+  | \t      ^~~~
+"
+        );
+    }
+
+    #[test]
+    fn empty_span_at_end_of_input() {
+        let source = "print 1";
+        let diagnostic = Diagnostic::new(
+            "E0100",
+            "expected ';' (SEMICOLON), found EOF",
+            Span {
+                start: 7,
+                end: 7,
+                line: 1,
+                column: 8,
+            },
+        );
+
+        assert!(diagnostic
+            .render("main.lox", source, false)
+            .ends_with("1 | print 1\n  |        ^\n"));
+    }
+
+    #[test]
+    fn only_the_location_without_source() {
+        let diagnostic = Diagnostic::new("E0201", "Undefined variable 'a'.", Span::default())
+            .with_help("declare it with 'var'");
+
+        assert_eq!(
+            diagnostic.render("main.lox", "", false),
+            "error[E0201]: Undefined variable 'a'.
+ --> main.lox:0:0
+  = help: declare it with 'var'
+"
+        );
+    }
+}
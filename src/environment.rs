@@ -1,50 +1,128 @@
-use crate::literal::Literal;
-use std::collections::HashMap;
+use crate::{
+    gc::{self, Trace, Tracer},
+    literal::Literal,
+    native::{self, NativeFunction},
+};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
+struct Scope {
+    variables: HashMap<String, Literal>,
+    enclosing: Option<Rc<RefCell<Scope>>>,
+}
+
+/// Handle to the innermost scope of a chain of scopes. Scopes live on the heap
+/// so a closure can keep the one it was defined in alive after the block that
+/// created it has finished, cloning an `Environment` shares the same scopes.
+#[derive(Debug, Clone)]
 pub struct Environment {
-    variables: Vec<HashMap<String, Literal>>,
+    scope: Rc<RefCell<Scope>>,
+    /// The outermost scope, where top level declarations live
+    globals: Rc<RefCell<Scope>>,
 }
+
 impl Environment {
+    /// A global scope with the native functions and `math` already defined
     pub fn new() -> Self {
+        let scope = gc::manage(RefCell::new(Scope::default()));
+        let mut environment = Self {
+            globals: scope.clone(),
+            scope,
+        };
+        native::define_globals(&mut environment);
+        environment
+    }
+
+    /// Makes a Rust function callable from Lox as a global, replacing anything
+    /// already defined with the same name
+    pub fn define_native(&mut self, native: NativeFunction) {
+        self.globals
+            .borrow_mut()
+            .variables
+            .insert(native.name.clone(), Literal::Native(native.into()));
+    }
+
+    /// A new environment with a fresh scope whose parent is the current scope
+    pub fn enclosed(&self) -> Self {
         Self {
-            variables: vec![HashMap::new()],
+            scope: gc::manage(RefCell::new(Scope {
+                variables: HashMap::new(),
+                enclosing: Some(self.scope.clone()),
+            })),
+            globals: self.globals.clone(),
         }
     }
-    pub fn push_scope(&mut self) {
-        self.variables.push(HashMap::new());
-    }
-    pub fn pop_scope(&mut self) {
-        assert_ne!(self.variables.len(), 1, "cannot pop scope when scope is 1");
-        self.variables.pop();
+
+    /// Defines `key` in the innermost scope
+    pub fn insert(&mut self, key: String, value: Literal) -> Option<Literal> {
+        self.scope.borrow_mut().variables.insert(key, value)
     }
 
-    pub fn scope(&self) -> usize {
-        self.variables.len()
+    /// Looks `key` up in the scope `depth` levels out, as worked out by the
+    /// resolver, a depth of `None` means it is a global.
+    pub fn get(&self, key: &str, depth: Option<usize>) -> Option<Literal> {
+        self.ancestor(depth).borrow().variables.get(key).cloned()
     }
-    pub fn insert(&mut self, key: String, value: Literal) -> Option<Literal> {
-        self.variables
-            .last_mut()
-            .expect("should have at least one element")
-            .insert(key, value)
-    }
-    pub fn get(&self, key: &str) -> Option<&Literal> {
-        for map in self.variables.iter().rev() {
-            let value = map.get(key);
-            if value.is_some() {
-                return value;
+
+    /// Overwrites the variable called `key` in the scope `depth` levels out,
+    /// returns false if it isn't defined there.
+    pub fn assign(&mut self, key: &str, depth: Option<usize>, value: Literal) -> bool {
+        match self.ancestor(depth).borrow_mut().variables.get_mut(key) {
+            Some(variable) => {
+                *variable = value;
+                true
             }
+            None => false,
         }
-        None
     }
 
-    pub fn get_mut(&mut self, key: &str) -> Option<&mut Literal> {
-        for map in self.variables.iter_mut().rev() {
-            let value = map.get_mut(key);
-            if value.is_some() {
-                return value;
-            }
+    /// Reports the scopes the environment holds onto to the collector
+    pub(crate) fn trace(&self, tracer: &mut Tracer) {
+        tracer.object(&self.scope);
+        tracer.object(&self.globals);
+    }
+
+    fn ancestor(&self, depth: Option<usize>) -> Rc<RefCell<Scope>> {
+        let Some(depth) = depth else {
+            return self.globals.clone();
+        };
+        let mut scope = self.scope.clone();
+        for _ in 0..depth {
+            let enclosing = scope
+                .borrow()
+                .enclosing
+                .clone()
+                .expect("the resolver only hands out depths of scopes that exist");
+            scope = enclosing;
+        }
+        scope
+    }
+}
+
+impl Trace for RefCell<Scope> {
+    fn trace(&self, tracer: &mut Tracer) {
+        let Ok(scope) = self.try_borrow() else {
+            return tracer.busy();
+        };
+        scope
+            .variables
+            .values()
+            .for_each(|value| tracer.literal(value));
+        if let Some(enclosing) = &scope.enclosing {
+            tracer.object(enclosing);
         }
-        None
+    }
+
+    fn clear(&self) {
+        if let Ok(mut scope) = self.try_borrow_mut() {
+            scope.variables.clear();
+            scope.enclosing = None;
+        }
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
     }
 }
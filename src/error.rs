@@ -0,0 +1,46 @@
+use crate::span::Span;
+use std::fmt::Display;
+
+/// Raised while executing a program, eg. adding a number to a string or
+/// reading a variable that was never declared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub span: Span,
+    /// Diagnostic code, `E02xx` for runtime errors
+    pub code: &'static str,
+    pub help: Option<String>,
+}
+
+impl RuntimeError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            code: "E0200",
+            help: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[line {}] RuntimeError: {}",
+            self.span.line, self.message
+        )
+    }
+}
+
+impl std::error::Error for RuntimeError {}
@@ -0,0 +1,123 @@
+use crate::{
+    environment::Environment,
+    error::RuntimeError,
+    gc::{Trace, Tracer},
+    literal::Literal,
+    parser::statement::{execute_block, Flow, FunctionDecl},
+    span::Span,
+};
+use std::{cell::Cell, fmt::Debug, io::Write, rc::Rc};
+
+/// How deep calls can nest before the tree-walker reports a stack overflow.
+/// Every call recurses on the native stack, so the thread running the program
+/// needs room for this many.
+const MAX_CALL_DEPTH: usize = 1024;
+
+thread_local! {
+    /// Calls the tree-walker is in the middle of. It's counted per thread
+    /// rather than per interpreter because what runs out is the thread's stack.
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counts a call for as long as it's held
+pub struct CallGuard(());
+
+impl CallGuard {
+    /// Fails with a stack overflow at `span` when too many calls are already
+    /// running
+    pub fn enter(span: Span) -> Result<CallGuard, RuntimeError> {
+        CALL_DEPTH.with(|depth| {
+            if depth.get() == MAX_CALL_DEPTH {
+                return Err(RuntimeError::new("Stack overflow.", span).with_code("E0208"));
+            }
+            depth.set(depth.get() + 1);
+            Ok(CallGuard(()))
+        })
+    }
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        CALL_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+pub struct Function {
+    declaration: Rc<FunctionDecl>,
+    /// The environment the function was declared in
+    closure: Environment,
+    /// `init` methods always hand back `this`, whatever the body returns
+    is_initializer: bool,
+}
+
+impl Function {
+    pub fn new(declaration: Rc<FunctionDecl>, closure: Environment, is_initializer: bool) -> Self {
+        Self {
+            declaration,
+            closure,
+            is_initializer,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.declaration.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    /// Makes a copy of the method with `this` defined in a scope between the
+    /// closure and the body.
+    pub fn bind(&self, instance: Literal) -> Function {
+        let mut closure = self.closure.enclosed();
+        closure.insert("this".to_string(), instance);
+        Function::new(self.declaration.clone(), closure, self.is_initializer)
+    }
+
+    /// Runs the body in a new scope on top of the closure, so the function sees
+    /// the variables from where it was declared rather than where it was called.
+    pub fn call<W: Write>(
+        &self,
+        arguments: Vec<Literal>,
+        output: &mut W,
+    ) -> Result<Literal, RuntimeError> {
+        let mut env = self.closure.enclosed();
+        for (param, argument) in self.declaration.params.iter().zip(arguments) {
+            env.insert(param.clone(), argument);
+        }
+
+        let flow = execute_block(&self.declaration.body, &mut env, output)?;
+
+        if self.is_initializer {
+            return Ok(self
+                .closure
+                .get("this", Some(0))
+                .expect("initializers are always bound to an instance"));
+        }
+        Ok(match flow {
+            Flow::Return(value) => value,
+            Flow::Normal => Literal::Nil,
+            Flow::Break | Flow::Continue => {
+                unreachable!("the parser only allows break and continue inside loops")
+            }
+        })
+    }
+}
+
+impl Trace for Function {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.closure.trace(tracer);
+    }
+}
+
+// The closure usually contains the function itself, so it is left out here to
+// avoid recursing forever.
+impl Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.declaration.name)
+            .field("params", &self.declaration.params)
+            .finish_non_exhaustive()
+    }
+}
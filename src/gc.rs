@@ -0,0 +1,567 @@
+//! A mark-and-sweep collector for the objects values can refer to: scopes,
+//! functions, classes, instances, lists, maps and the VM's closures and
+//! upvalues.
+//!
+//! Objects are handed around as `Rc` handles, and every one made while an
+//! interpreter is running is created through [`manage`], which keeps a weak
+//! handle to it in that interpreter's [`Heap`]. Reference counting still frees
+//! most objects as soon as they're unused. What it can't free is a cycle, like
+//! a closure stored in the scope it captured or a list put inside itself, and
+//! that's what the collector is for.
+//!
+//! Allocating only notes that a collection is due. It runs at the next safe
+//! point, the start of a statement in the tree-walker or of an instruction in
+//! the VM, where every value the program holds can be found from the roots:
+//!
+//! - the global scope of every interpreter using the heap
+//! - the VM's stack, call frames and open upvalues
+//! - the scopes of the blocks and calls the tree-walker is inside, and values
+//!   it's holding in Rust halfway through an expression, like the left operand
+//!   while the right one runs, which it pushes on a root stack with [`Rooted`]
+//! - values that have passed between Lox and Rust, which are [`pin`]ned
+//!
+//! Mark: everything reachable from a root is marked, following references
+//! into objects whatever heap they're in. Sweep: the heap's unmarked objects
+//! are cleared, which breaks their cycles and lets reference counting free
+//! them.
+//!
+//! Rust code can keep values it got from the interpreter, from
+//! [`get_global`](crate::Interpreter::get_global), `eval_expr` or as the
+//! arguments to a native, for as long as it likes, so those values are
+//! pinned. A pinned value is a root until nothing holds it any more, which
+//! means a cycle that includes one is never freed. Objects made while no
+//! interpreter is running aren't in any heap and are only reference counted.
+
+use crate::literal::Literal;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    rc::{Rc, Weak},
+};
+
+/// How often collections run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcConfig {
+    /// How many objects the heap can hold before the first collection, and
+    /// the fewest any later collection waits for
+    pub initial_threshold: usize,
+    /// After a collection the next one waits until the heap is this many
+    /// times the size of what survived
+    pub growth_factor: usize,
+    /// Collect at the first safe point after every allocation, to find
+    /// objects that aren't traced or values that aren't rooted
+    pub stress: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            initial_threshold: 10_000,
+            growth_factor: 2,
+            stress: false,
+        }
+    }
+}
+
+impl GcConfig {
+    /// The default thresholds, but collecting after every allocation
+    pub fn stress() -> Self {
+        Self {
+            stress: true,
+            ..Self::default()
+        }
+    }
+}
+
+/// What the collector has done for a heap so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GcStats {
+    pub collections: usize,
+    /// Objects cleared because they were unreachable
+    pub freed: usize,
+    /// Objects in the heap after the last collection
+    pub live: usize,
+}
+
+/// An object in the heap
+pub(crate) trait Trace {
+    /// Reports every object the object holds, see [`Tracer`]
+    fn trace(&self, tracer: &mut Tracer);
+
+    /// Drops the references the object holds, called once it's known to be
+    /// unreachable. Objects that can't be changed after they're created can't
+    /// be part of a cycle on their own, so they have nothing to do.
+    fn clear(&self) {}
+}
+
+/// Collects the objects an object or a root holds. Anything left out can be
+/// cleared while it's still in use.
+#[derive(Default)]
+pub(crate) struct Tracer {
+    children: Vec<Rc<dyn Trace>>,
+    /// The object is borrowed and what it holds can't be read
+    busy: bool,
+}
+
+impl Tracer {
+    pub(crate) fn object<T: Trace + 'static>(&mut self, object: &Rc<T>) {
+        self.children.push(object.clone());
+    }
+
+    pub(crate) fn literal(&mut self, value: &Literal) {
+        match value {
+            // natives are Rust closures, what they hold is up to Rust
+            Literal::Number(_)
+            | Literal::String(_)
+            | Literal::True
+            | Literal::False
+            | Literal::Nil
+            | Literal::Native(_) => {}
+            Literal::Function(function) => self.object(function),
+            Literal::Class(class) => self.object(class),
+            Literal::Instance(instance) => self.object(instance),
+            Literal::List(list) => self.object(list),
+            Literal::Map(map) => self.object(map),
+            Literal::Closure(closure) => self.object(closure),
+            Literal::BoundMethod(method) => self.object(method),
+            Literal::VmClass(class) => self.object(class),
+            Literal::VmInstance(instance) => self.object(instance),
+        }
+    }
+
+    /// For objects that are borrowed while the collector runs. Nothing can
+    /// be known to be unreachable then, so the collection is put off.
+    pub(crate) fn busy(&mut self) {
+        self.busy = true;
+    }
+}
+
+/// The objects allocated while an interpreter was running, and when to
+/// collect them
+pub(crate) struct Heap {
+    objects: Vec<Weak<dyn Trace>>,
+    /// The global scopes of the interpreters using the heap
+    globals: Vec<Rc<dyn Trace>>,
+    /// Collect once the heap holds this many objects
+    next_gc: usize,
+    /// Something was allocated in stress mode or the heap is full
+    due: bool,
+    config: GcConfig,
+    stats: GcStats,
+}
+
+thread_local! {
+    /// Where objects are allocated right now, `None` outside any interpreter
+    static CURRENT: RefCell<Option<Rc<RefCell<Heap>>>> = const { RefCell::new(None) };
+
+    /// Whether the current heap might be due a collection, checked at every
+    /// safe point so it has to be quick
+    static PENDING: Cell<bool> = const { Cell::new(false) };
+
+    /// Values held in Rust by the code running on this thread, see [`Rooted`]
+    static ROOTS: RefCell<Vec<Rc<dyn Trace>>> = const { RefCell::new(vec![]) };
+
+    /// Objects Rust has been given, a root for every heap while they're alive
+    static PINNED: RefCell<HashMap<*const (), Weak<dyn Trace>>> = RefCell::new(HashMap::new());
+}
+
+/// Allocates `value` in the current heap
+pub(crate) fn manage<T: Trace + 'static>(value: T) -> Rc<T> {
+    let object = Rc::new(value);
+    CURRENT.with_borrow(|heap| {
+        let Some(heap) = heap else {
+            return;
+        };
+        let mut heap = heap.borrow_mut();
+        let handle: Weak<dyn Trace> = Rc::downgrade(&object) as Weak<dyn Trace>;
+        heap.objects.push(handle);
+        if heap.config.stress || heap.objects.len() >= heap.next_gc {
+            heap.due = true;
+            PENDING.set(true);
+        }
+    });
+    object
+}
+
+/// Collects the current heap if it's due. `roots` reports the values the
+/// caller holds, on top of the globals and what's rooted or pinned.
+pub(crate) fn safe_point(roots: impl FnOnce(&mut Tracer)) {
+    if !PENDING.replace(false) {
+        return;
+    }
+    let Some(heap) = CURRENT.with_borrow(Clone::clone) else {
+        return;
+    };
+    let mut heap = heap.borrow_mut();
+    if heap.due {
+        heap.collect(roots);
+    }
+}
+
+/// Makes `heap` the current heap until the returned guard is dropped
+pub(crate) fn enter(heap: &Rc<RefCell<Heap>>) -> Entered {
+    PENDING.set(true);
+    Entered(CURRENT.replace(Some(heap.clone())))
+}
+
+/// Puts back the heap that was current before [`enter`]
+pub(crate) struct Entered(Option<Rc<RefCell<Heap>>>);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        PENDING.set(true);
+        CURRENT.set(self.0.take());
+    }
+}
+
+/// Values the running code holds in Rust, outside any object, that are
+/// roots until the guard is dropped. Guards have to be dropped in the reverse
+/// order they were created in, and a guard can only be added to while it's
+/// the newest one.
+#[must_use]
+pub(crate) struct Rooted {
+    /// The length of the root stack before anything was pushed
+    base: Option<usize>,
+}
+
+impl Rooted {
+    pub(crate) fn new() -> Self {
+        Self { base: None }
+    }
+
+    pub(crate) fn value(&mut self, value: &Literal) {
+        self.trace(|roots| roots.literal(value));
+    }
+
+    pub(crate) fn trace(&mut self, trace: impl FnOnce(&mut Tracer)) {
+        let mut tracer = Tracer::default();
+        trace(&mut tracer);
+        if tracer.children.is_empty() {
+            return;
+        }
+        ROOTS.with_borrow_mut(|roots| {
+            self.base.get_or_insert(roots.len());
+            roots.append(&mut tracer.children);
+        });
+    }
+}
+
+impl Drop for Rooted {
+    fn drop(&mut self) {
+        if let Some(base) = self.base {
+            ROOTS.with_borrow_mut(|roots| roots.truncate(base));
+        }
+    }
+}
+
+/// Roots `value` until the returned guard is dropped
+pub(crate) fn root(value: &Literal) -> Rooted {
+    let mut rooted = Rooted::new();
+    rooted.value(value);
+    rooted
+}
+
+/// Keeps `value` and everything it refers to from being collected while
+/// anything holds it, for values handed between Lox and Rust
+pub(crate) fn pin(value: &Literal) {
+    let mut tracer = Tracer::default();
+    tracer.literal(value);
+    PINNED.with_borrow_mut(|pinned| {
+        for object in tracer.children {
+            pinned.insert(
+                Rc::as_ptr(&object) as *const (),
+                Rc::downgrade(&object) as Weak<dyn Trace>,
+            );
+        }
+    });
+}
+
+impl Heap {
+    pub(crate) fn new(config: GcConfig) -> Self {
+        Self {
+            objects: vec![],
+            globals: vec![],
+            next_gc: config.initial_threshold,
+            due: false,
+            config,
+            stats: GcStats::default(),
+        }
+    }
+
+    pub(crate) fn configure(&mut self, config: GcConfig) {
+        self.config = config;
+        self.next_gc = config.initial_threshold;
+    }
+
+    /// Makes the globals an interpreter reports to `trace` roots for as long
+    /// as the heap lives
+    pub(crate) fn add_globals(&mut self, trace: impl FnOnce(&mut Tracer)) {
+        let mut tracer = Tracer::default();
+        trace(&mut tracer);
+        self.globals.append(&mut tracer.children);
+    }
+
+    /// Collects whatever is left once the heap's interpreter is gone. The
+    /// objects still in use are only reference counted from then on.
+    pub(crate) fn retire(&mut self) {
+        self.globals.clear();
+        self.collect(|_| {});
+        self.objects.clear();
+    }
+
+    fn collect(&mut self, roots: impl FnOnce(&mut Tracer)) {
+        let mut tracer = Tracer::default();
+        roots(&mut tracer);
+        let mut pending = tracer.children;
+        pending.extend(self.globals.iter().cloned());
+        ROOTS.with_borrow(|roots| pending.extend(roots.iter().cloned()));
+        PINNED.with_borrow_mut(|pinned| {
+            pinned.retain(|_, object| object.strong_count() > 0);
+            pending.extend(pinned.values().filter_map(Weak::upgrade));
+        });
+
+        let mut marked = HashSet::new();
+        while let Some(object) = pending.pop() {
+            if !marked.insert(Rc::as_ptr(&object) as *const ()) {
+                continue;
+            }
+            let mut tracer = Tracer::default();
+            object.trace(&mut tracer);
+            if tracer.busy {
+                // tried again at the next safe point
+                PENDING.set(true);
+                return;
+            }
+            pending.append(&mut tracer.children);
+        }
+
+        // holding these keeps every object alive until the sweep is done
+        let objects: Vec<Rc<dyn Trace>> = self.objects.iter().filter_map(Weak::upgrade).collect();
+        let mut freed = 0;
+        for object in &objects {
+            if !marked.contains(&(Rc::as_ptr(object) as *const ())) {
+                object.clear();
+                freed += 1;
+            }
+        }
+        drop(objects);
+        self.objects.retain(|object| object.strong_count() > 0);
+
+        let live = self.objects.len();
+        self.next_gc = (live * self.config.growth_factor).max(self.config.initial_threshold);
+        self.due = false;
+        self.stats.collections += 1;
+        self.stats.freed += freed;
+        self.stats.live = live;
+    }
+}
+
+impl Trace for RefCell<Vec<Literal>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        let Ok(elements) = self.try_borrow() else {
+            return tracer.busy();
+        };
+        elements.iter().for_each(|element| tracer.literal(element));
+    }
+
+    fn clear(&self) {
+        if let Ok(mut elements) = self.try_borrow_mut() {
+            elements.clear();
+        }
+    }
+}
+
+impl<K> Trace for RefCell<std::collections::BTreeMap<K, Literal>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        let Ok(entries) = self.try_borrow() else {
+            return tracer.busy();
+        };
+        entries.values().for_each(|value| tracer.literal(value));
+    }
+
+    fn clear(&self) {
+        if let Ok(mut entries) = self.try_borrow_mut() {
+            entries.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{enter, root, GcConfig, Heap};
+    use crate::{
+        interpreter::{Backend, CapturedOutput, Interpreter},
+        literal::Literal,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn heap() -> Rc<RefCell<Heap>> {
+        Rc::new(RefCell::new(Heap::new(GcConfig::default())))
+    }
+
+    #[test]
+    fn frees_cycles() {
+        let heap = heap();
+        let _heap = enter(&heap);
+        let list: Literal = vec![Literal::Nil].into();
+        let Literal::List(elements) = &list else {
+            unreachable!()
+        };
+        elements.borrow_mut()[0] = list.clone();
+        let handle = Rc::downgrade(elements);
+        drop(list);
+        assert!(handle.upgrade().is_some(), "the list keeps itself alive");
+        heap.borrow_mut().collect(|_| {});
+        assert!(handle.upgrade().is_none());
+        assert_eq!(heap.borrow().stats.freed, 1);
+    }
+
+    #[test]
+    fn keeps_what_is_still_used() {
+        let heap = heap();
+        let _heap = enter(&heap);
+        let list: Literal = vec![Literal::Nil, 1.0.into()].into();
+        let Literal::List(elements) = &list else {
+            unreachable!()
+        };
+        elements.borrow_mut()[0] = list.clone();
+        let handle = Rc::downgrade(elements);
+        // only reachable through another object in the heap now
+        let outer: Literal = vec![list].into();
+        let _outer = root(&outer);
+        heap.borrow_mut().collect(|_| {});
+        let elements = handle.upgrade().expect("the list is still in use");
+        assert!(
+            matches!(&elements.borrow()[0], Literal::List(inner) if Rc::ptr_eq(inner, &elements))
+        );
+        assert_eq!(elements.borrow()[1], Literal::Number(1.0));
+        drop(outer);
+    }
+
+    /// Each iteration leaves a closure, a class and an instance behind that
+    /// only keep each other alive
+    const CYCLES: &str = "for (var i = 0; i < 500; i = i + 1) {
+        fun recursive() { return recursive; }
+        class Node { init() { this.self = this; } }
+        var node = Node();
+        var list = [node];
+        list[0] = list;
+    }";
+
+    fn interpreter(backend: Backend, gc: GcConfig) -> (Interpreter, CapturedOutput) {
+        let output = CapturedOutput::new();
+        let interpreter = Interpreter::new()
+            .with_backend(backend)
+            .with_gc(gc)
+            .with_stdout(output.clone());
+        (interpreter, output)
+    }
+
+    fn run(src: &str, backend: Backend, gc: GcConfig) -> String {
+        let (mut interpreter, output) = interpreter(backend, gc);
+        interpreter.run_source(src).expect("the program runs");
+        output.contents()
+    }
+
+    #[test]
+    fn collects_while_programs_run() {
+        let gc = GcConfig {
+            initial_threshold: 100,
+            ..GcConfig::default()
+        };
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (mut interpreter, _) = interpreter(backend, gc);
+            interpreter.run_source(CYCLES).unwrap();
+            let mut heap = interpreter.heap().borrow_mut();
+            heap.collect(|_| {});
+            let stats = heap.stats;
+            assert!(stats.collections > 5, "{backend:?}");
+            assert!(stats.freed >= 500 * 4, "{backend:?}");
+            assert!(stats.live < 200, "{backend:?} left {} objects", stats.live);
+        }
+    }
+
+    #[test]
+    fn interpreters_have_their_own_heaps() {
+        let (mut stressed, _) = interpreter(Backend::TreeWalker, GcConfig::stress());
+        let (mut relaxed, _) = interpreter(Backend::TreeWalker, GcConfig::default());
+        relaxed.run_source(CYCLES).unwrap();
+        assert_eq!(relaxed.heap().borrow().stats.collections, 0);
+        assert_eq!(stressed.heap().borrow().stats.collections, 0);
+
+        // a native running one interpreter from inside another allocates in
+        // the inner one's heap until it returns
+        let inner = std::cell::RefCell::new(relaxed);
+        let inner = Rc::new(inner);
+        let nested = inner.clone();
+        stressed.define_native(crate::native::NativeFunction::new("nested", 0, move |_| {
+            nested.borrow_mut().run_source("var list = [[1]];").unwrap();
+            Ok(Literal::Nil)
+        }));
+        stressed
+            .run_source("nested(); var list = [[2]]; print list;")
+            .unwrap();
+        assert!(stressed.heap().borrow().stats.collections > 0);
+        assert_eq!(inner.borrow().heap().borrow().stats.collections, 0);
+        assert_eq!(
+            inner.borrow().get_global("list").unwrap().to_string(),
+            "[[1]]"
+        );
+    }
+
+    #[test]
+    fn dropping_an_interpreter_frees_its_globals() {
+        let (mut interpreter, _) = interpreter(Backend::TreeWalker, GcConfig::default());
+        interpreter
+            .run_source("fun recursive() { return recursive; } var list = [1]; list[0] = list;")
+            .unwrap();
+        // not through get_global, which would pin it
+        let function = match interpreter.environment().get("recursive", None) {
+            Some(Literal::Function(function)) => Rc::downgrade(&function),
+            _ => panic!("expected a function"),
+        };
+        // still in use after the interpreter is gone
+        let Some(Literal::List(list)) = interpreter.get_global("list") else {
+            panic!("expected a list");
+        };
+        drop(interpreter);
+        assert!(function.upgrade().is_none());
+        assert_eq!(Literal::List(list).to_string(), "[[...]]");
+    }
+
+    #[test]
+    fn values_handed_to_rust_are_kept() {
+        let (mut interpreter, _) = interpreter(Backend::TreeWalker, GcConfig::stress());
+        let list = interpreter.eval_expr("[[1], {}]").unwrap();
+        interpreter.run_source("var a = [1]; var b = [a];").unwrap();
+        assert!(interpreter.heap().borrow().stats.collections > 0);
+        assert_eq!(list.to_string(), "[[1], {}]");
+    }
+
+    #[test]
+    fn stress_mode_changes_nothing() {
+        let src = "fun counter() {
+            var count = 0;
+            fun increment() { count = count + 1; return count; }
+            return increment;
+        }
+        class A { init(n) { this.n = n; this.list = [this]; } get() { return this.n; } }
+        class B < A { get() { return super.get() * 2; } }
+        var next = counter();
+        var objects = {};
+        for (var i = 0; i < 20; i = i + 1) {
+            objects[i] = B(next()).get;
+        }
+        print objects[19]() + len(objects);
+        print \"${[1, [2]]} ${\"a,b\".split(\",\")}\";";
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let expected = run(src, backend, GcConfig::default());
+            assert_eq!(
+                run(src, backend, GcConfig::stress()),
+                expected,
+                "{backend:?}"
+            );
+            assert_eq!(expected, "60\n[1, [2]] [\"a\", \"b\"]\n");
+        }
+    }
+}
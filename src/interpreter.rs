@@ -0,0 +1,679 @@
+use crate::{
+    diagnostic::Diagnostic,
+    environment::Environment,
+    error::RuntimeError,
+    gc::{self, GcConfig, Heap},
+    literal::Literal,
+    native::NativeFunction,
+    optimizer,
+    parser::{
+        statement::{Stmt, StmtKind},
+        Parser,
+    },
+    resolver, scanner,
+    vm::{
+        self,
+        bytecode::{self, BytecodeError},
+        chunk::CompiledFunction,
+    },
+    Context,
+};
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    fs,
+    io::{self, IsTerminal, Write},
+    path::Path,
+    rc::Rc,
+};
+
+/// What diagnostics call source passed to [`Interpreter::run_source`] unless
+/// [`Interpreter::with_name`] says otherwise
+const SOURCE_NAME: &str = "<source>";
+
+/// How programs are executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Evaluate the AST directly
+    #[default]
+    TreeWalker,
+    /// Compile to bytecode and run it on a stack machine
+    Vm,
+}
+
+/// Everything needed to run Lox code: the global scope, where `print` and
+/// diagnostics go, and the natives programs can call. Globals are kept between
+/// runs, so a variable defined by one `run_source` can be used by the next.
+pub struct Interpreter {
+    environment: Environment,
+    /// Everything the interpreter's programs allocate, it's the thread's
+    /// current heap while they run
+    heap: Rc<RefCell<Heap>>,
+    stdout: Box<dyn Write>,
+    stderr: Box<dyn Write>,
+    /// Whether diagnostics are written with colours
+    colour: bool,
+    name: String,
+    backend: Backend,
+    /// Whether programs go through the optimizer before they run
+    optimize: bool,
+}
+
+/// Why running some code failed. The diagnostics have already been written to
+/// the interpreter's stderr by the time this is returned.
+#[derive(Debug)]
+pub enum InterpretError {
+    /// The file couldn't be read
+    Io(io::Error),
+    /// Scanner, parser or resolver errors, nothing was run
+    Static(Vec<Diagnostic>),
+    /// A bytecode file that couldn't be loaded, nothing was run
+    Bytecode(BytecodeError),
+    Runtime(RuntimeError),
+}
+
+/// A sink that keeps everything written to it, for capturing what a program
+/// prints. Clones share the same buffer, so one clone can be handed to
+/// [`Interpreter::with_stdout`] and the other read afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct CapturedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl CapturedOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written so far, invalid UTF-8 is replaced
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    /// Empties the buffer
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+impl Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl InterpretError {
+    /// Exit code for a script that failed this way, following sysexits.h
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            InterpretError::Io(_) => 66,
+            InterpretError::Static(_) | InterpretError::Bytecode(_) => 65,
+            InterpretError::Runtime(_) => 70,
+        }
+    }
+}
+
+impl Display for InterpretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpretError::Io(error) => write!(f, "{error}"),
+            InterpretError::Static(diagnostics) => {
+                write!(f, "{} error(s) before running", diagnostics.len())
+            }
+            InterpretError::Bytecode(error) => write!(f, "{error}"),
+            InterpretError::Runtime(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for InterpretError {}
+
+impl Interpreter {
+    /// Prints to the process's stdout and stderr, in colour if stderr is a
+    /// terminal and `NO_COLOR` isn't set
+    pub fn new() -> Self {
+        let heap = Rc::new(RefCell::new(Heap::new(GcConfig::default())));
+        let environment = {
+            let _heap = gc::enter(&heap);
+            Environment::new()
+        };
+        heap.borrow_mut()
+            .add_globals(|roots| environment.trace(roots));
+        Self {
+            environment,
+            heap,
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            colour: io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            name: SOURCE_NAME.to_string(),
+            backend: Backend::default(),
+            optimize: false,
+        }
+    }
+
+    /// Sends everything `print`ed to `stdout`
+    pub fn with_stdout(mut self, stdout: impl Write + 'static) -> Self {
+        self.stdout = Box::new(stdout);
+        self
+    }
+
+    /// Sends diagnostics to `stderr`, without colours
+    pub fn with_stderr(mut self, stderr: impl Write + 'static) -> Self {
+        self.stderr = Box::new(stderr);
+        self.colour = false;
+        self
+    }
+
+    /// What diagnostics call the code passed to `run_source` and `eval_expr`
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Which backend runs the code, both share the same globals
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Whether to fold constants and drop branches that can never run before
+    /// running or compiling, off by default
+    pub fn with_optimizer(mut self, enabled: bool) -> Self {
+        self.optimize = enabled;
+        self
+    }
+
+    /// How often the garbage collector runs, each interpreter has its own heap
+    pub fn with_gc(self, config: GcConfig) -> Self {
+        self.heap.borrow_mut().configure(config);
+        self
+    }
+
+    /// Makes a Rust function callable from Lox as a global
+    pub fn define_native(&mut self, native: NativeFunction) {
+        // Rust can hold onto the arguments after the call
+        let (name, arity) = (native.name.clone(), native.arity);
+        self.environment
+            .define_native(NativeFunction::new(name, arity, move |arguments| {
+                arguments.iter().for_each(gc::pin);
+                native.call(arguments)
+            }));
+    }
+
+    /// Defines a global variable, replacing any existing one with that name
+    pub fn set_global(&mut self, name: impl Into<String>, value: impl Into<Literal>) {
+        self.environment.insert(name.into(), value.into());
+    }
+
+    /// The value of a global variable, `None` if it isn't defined
+    pub fn get_global(&self, name: &str) -> Option<Literal> {
+        self.environment.get(name, None).inspect(gc::pin)
+    }
+
+    /// Runs a whole program
+    pub fn run_source(&mut self, source: &str) -> Result<(), InterpretError> {
+        let name = self.name.clone();
+        self.run(&name, source)
+    }
+
+    /// Runs the program in the file at `path`, diagnostics are reported
+    /// against the path. Files written by the `compile` command are detected
+    /// and run on the VM whichever backend is set.
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<(), InterpretError> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let bytes = fs::read(path).map_err(|error| self.read_error(&name, error))?;
+        if bytecode::is_bytecode(&bytes) {
+            return self.load_and_run(&name, &bytes);
+        }
+        let source = String::from_utf8(bytes).map_err(|error| {
+            self.read_error(&name, io::Error::new(io::ErrorKind::InvalidData, error))
+        })?;
+        self.run(&name, &source)
+    }
+
+    /// Runs a script made by [`Interpreter::compile`] on the VM, whichever
+    /// backend is set. Runtime errors point at the file it was compiled from,
+    /// without showing its source.
+    pub fn run_bytecode(&mut self, bytes: &[u8]) -> Result<(), InterpretError> {
+        let name = self.name.clone();
+        self.load_and_run(&name, bytes)
+    }
+
+    /// Evaluates a single expression, like `1 + 2`, and returns its value
+    pub fn eval_expr(&mut self, source: &str) -> Result<Literal, InterpretError> {
+        let _heap = gc::enter(&self.heap);
+        let name = self.name.clone();
+        let mut context = Context::new();
+        let tokens = scanner::tokenize(source, &mut context);
+        let expression = Parser::new(tokens).expression();
+        let expression = match expression {
+            Ok(expression) if context.errors.is_empty() => expression,
+            Ok(_) => return Err(self.static_errors(&name, source, context)),
+            Err(error) => {
+                context.parse_error(error);
+                return Err(self.static_errors(&name, source, context));
+            }
+        };
+        // the resolver works on statements, and still has `this` and `super`
+        // to check even though every variable here is a global
+        let span = expression.span;
+        let mut program = [Stmt::new(StmtKind::Expression(expression), span)];
+        resolver::resolve(&mut program, &mut context);
+        if !context.errors.is_empty() {
+            return Err(self.static_errors(&name, source, context));
+        }
+        let [Stmt {
+            kind: StmtKind::Expression(expression),
+            ..
+        }] = program
+        else {
+            unreachable!("the resolver doesn't change what kind a statement is")
+        };
+        let expression = if self.optimize {
+            optimizer::optimize_expression(expression)
+        } else {
+            expression
+        };
+        let value = match self.backend {
+            Backend::TreeWalker => expression.evaluate(&mut self.environment, &mut self.stdout),
+            Backend::Vm => {
+                let script = vm::compiler::compile_expression(&expression, &mut context);
+                if !context.errors.is_empty() {
+                    return Err(self.static_errors(&name, source, context));
+                }
+                vm::run(script, &mut self.environment, &mut self.stdout)
+            }
+        };
+        value
+            .inspect(gc::pin)
+            .map_err(|error| self.runtime_error(&name, source, error))
+    }
+
+    /// Writes every token in `source` to stdout, one per line as its type,
+    /// lexeme and literal. Tokens are written even when some of the source
+    /// couldn't be scanned.
+    pub fn tokenize(&mut self, source: &str) -> Result<(), InterpretError> {
+        let name = self.name.clone();
+        let mut context = Context::new();
+        let tokens = scanner::tokenize(source, &mut context);
+        let result = if context.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.static_errors(&name, source, context))
+        };
+        for token in tokens {
+            let token = token.token;
+            let line = format!(
+                "{} {} {}",
+                token.token_type(),
+                token.lexeme(),
+                token.literal()
+            );
+            writeln!(self.stdout, "{line}").map_err(InterpretError::Io)?;
+        }
+        result
+    }
+
+    /// Parses a single expression and writes it to stdout with every operation
+    /// in parentheses, like `(+ 1.0 (* 2.0 3.0))`
+    pub fn parse_expression(&mut self, source: &str) -> Result<(), InterpretError> {
+        let name = self.name.clone();
+        let mut context = Context::new();
+        let tokens = scanner::tokenize(source, &mut context);
+        let expression = Parser::new(tokens).expression();
+        let expression = match expression {
+            Ok(expression) => expression,
+            Err(error) => {
+                context.parse_error(error);
+                return Err(self.static_errors(&name, source, context));
+            }
+        };
+        let result = if context.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.static_errors(&name, source, context))
+        };
+        writeln!(self.stdout, "{}", expression.pretty_string()).map_err(InterpretError::Io)?;
+        result
+    }
+
+    /// Compiles a whole program without running it, into bytes that
+    /// [`Interpreter::run_bytecode`] and [`Interpreter::run_file`] can load
+    pub fn compile(&mut self, source: &str) -> Result<Vec<u8>, InterpretError> {
+        let script = self.compile_script(source)?;
+        Ok(bytecode::encode(&script, &self.name))
+    }
+
+    /// The VM's instructions for a whole program, as a readable listing
+    pub fn disassemble(&mut self, source: &str) -> Result<String, InterpretError> {
+        let script = self.compile_script(source)?;
+        Ok(vm::disassembler::disassemble(&script))
+    }
+
+    pub(crate) fn compile_script(
+        &mut self,
+        source: &str,
+    ) -> Result<Rc<CompiledFunction>, InterpretError> {
+        let name = self.name.clone();
+        let program = self.parse(&name, source)?;
+        self.compile_program(&name, source, &program)
+    }
+
+    fn run(&mut self, name: &str, source: &str) -> Result<(), InterpretError> {
+        let _heap = gc::enter(&self.heap);
+        let program = self.parse(name, source)?;
+        match self.backend {
+            Backend::TreeWalker => {
+                for statement in program {
+                    if let Err(error) = statement.execute(&mut self.environment, &mut self.stdout) {
+                        return Err(self.runtime_error(name, source, error));
+                    }
+                }
+            }
+            Backend::Vm => {
+                let script = self.compile_program(name, source, &program)?;
+                if let Err(error) = vm::run(script, &mut self.environment, &mut self.stdout) {
+                    return Err(self.runtime_error(name, source, error));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn load_and_run(&mut self, name: &str, bytes: &[u8]) -> Result<(), InterpretError> {
+        let _heap = gc::enter(&self.heap);
+        let (source_name, script) = bytecode::decode(bytes).map_err(|error| {
+            let _ = writeln!(self.stderr, "Failed to load {name}: {error}");
+            InterpretError::Bytecode(error)
+        })?;
+        vm::run(script, &mut self.environment, &mut self.stdout)
+            .map_err(|error| self.runtime_error(&source_name, "", error))?;
+        Ok(())
+    }
+
+    fn read_error(&mut self, name: &str, error: io::Error) -> InterpretError {
+        let _ = writeln!(self.stderr, "Failed to read file {name}: {error}");
+        InterpretError::Io(error)
+    }
+
+    /// Scans, parses, resolves and maybe optimizes a program
+    fn parse(&mut self, name: &str, source: &str) -> Result<Vec<Stmt>, InterpretError> {
+        let mut context = Context::new();
+        let tokens = scanner::tokenize(source, &mut context);
+        let mut program = Parser::new(tokens).parse().unwrap_or_else(|errors| {
+            errors
+                .into_iter()
+                .for_each(|error| context.parse_error(error));
+            vec![]
+        });
+        resolver::resolve(&mut program, &mut context);
+        if !context.errors.is_empty() {
+            return Err(self.static_errors(name, source, context));
+        }
+        if self.optimize {
+            program = optimizer::optimize(program);
+        }
+        Ok(program)
+    }
+
+    fn compile_program(
+        &mut self,
+        name: &str,
+        source: &str,
+        program: &[Stmt],
+    ) -> Result<Rc<CompiledFunction>, InterpretError> {
+        let mut context = Context::new();
+        let script = vm::compiler::compile(program, &mut context);
+        if !context.errors.is_empty() {
+            return Err(self.static_errors(name, source, context));
+        }
+        Ok(script)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn heap(&self) -> &Rc<RefCell<Heap>> {
+        &self.heap
+    }
+
+    #[cfg(test)]
+    pub(crate) fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    fn static_errors(&mut self, name: &str, source: &str, context: Context) -> InterpretError {
+        for error in &context.errors {
+            self.report(error, name, source);
+        }
+        InterpretError::Static(context.errors)
+    }
+
+    fn runtime_error(&mut self, name: &str, source: &str, error: RuntimeError) -> InterpretError {
+        self.report(&Diagnostic::from(&error), name, source);
+        InterpretError::Runtime(error)
+    }
+
+    fn report(&mut self, diagnostic: &Diagnostic, name: &str, source: &str) {
+        // anything printed before the error should show up before it
+        let _ = self.stdout.flush();
+        // blank line between diagnostics
+        let _ = writeln!(
+            self.stderr,
+            "{}",
+            diagnostic.render(name, source, self.colour)
+        );
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        // without the globals as roots, the cycles only they were keeping
+        // alive can be collected
+        self.heap.borrow_mut().retire();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Backend, CapturedOutput, InterpretError, Interpreter};
+    use crate::{literal::Literal, native::NativeFunction};
+    use std::io::{self, Write};
+
+    fn interpreter() -> (Interpreter, CapturedOutput, CapturedOutput) {
+        let (stdout, stderr) = (CapturedOutput::new(), CapturedOutput::new());
+        let interpreter = Interpreter::new()
+            .with_stdout(stdout.clone())
+            .with_stderr(stderr.clone());
+        (interpreter, stdout, stderr)
+    }
+
+    #[test]
+    fn globals_persist_between_runs() {
+        let (mut interpreter, stdout, stderr) = interpreter();
+        interpreter.run_source("var a = 1;").unwrap();
+        interpreter.run_source("a = a + 1; print a;").unwrap();
+        assert_eq!(
+            interpreter.eval_expr("a * 10").unwrap(),
+            Literal::Number(20.0)
+        );
+        assert_eq!(stdout.contents(), "2\n");
+        assert_eq!(stderr.contents(), "");
+    }
+
+    #[test]
+    fn errors_are_reported_to_stderr() {
+        let (interpreter, stdout, stderr) = interpreter();
+        let mut interpreter = interpreter.with_name("test.lox");
+        let result = interpreter.run_source("print 1;\nprint -\"a\";");
+        assert!(matches!(result, Err(InterpretError::Runtime(_))));
+        assert_eq!(result.unwrap_err().exit_code(), 70);
+        assert_eq!(stdout.contents(), "1\n");
+        assert!(stderr
+            .contents()
+            .starts_with("error[E0204]: Operand must be a number.\n --> test.lox:2:7\n"));
+
+        let result = interpreter.run_source("print 1;\nprint ;");
+        assert_eq!(result.unwrap_err().exit_code(), 65);
+        // nothing runs when there are static errors
+        assert_eq!(stdout.contents(), "1\n");
+
+        let result = interpreter.eval_expr("1 +");
+        assert!(matches!(result, Err(InterpretError::Static(errors)) if errors.len() == 1));
+    }
+
+    #[test]
+    fn expressions_are_resolved() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (interpreter, _, stderr) = interpreter();
+            let mut interpreter = interpreter.with_backend(backend);
+            for (source, code) in [("this", "E0105"), ("super.x", "E0108")] {
+                let result = interpreter.eval_expr(source);
+                assert!(
+                    matches!(&result, Err(InterpretError::Static(errors)) if errors[0].code == code),
+                    "{backend:?} {source}: {result:?}"
+                );
+            }
+            assert!(stderr
+                .contents()
+                .starts_with("error[E0105]: Can't use 'this' outside of a class.\n"));
+        }
+    }
+
+    #[test]
+    fn globals_from_rust() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (interpreter, stdout, _) = interpreter();
+            let mut interpreter = interpreter.with_backend(backend);
+            interpreter.set_global("greeting", "hi");
+            interpreter.set_global("count", 2.0);
+            interpreter
+                .run_source("print greeting; count = count + 1; var list = [count];")
+                .unwrap();
+            assert_eq!(interpreter.get_global("count"), Some(Literal::Number(3.0)));
+            assert_eq!(
+                interpreter.get_global("list"),
+                Some(vec![Literal::Number(3.0)].into())
+            );
+            assert_eq!(interpreter.get_global("missing"), None);
+            assert_eq!(stdout.contents(), "hi\n", "{backend:?}");
+
+            // a value set from Rust replaces the one the program defined
+            stdout.clear();
+            interpreter.set_global("count", "many");
+            interpreter
+                .run_source("print greeting + \" \" + count;")
+                .unwrap();
+            assert_eq!(stdout.contents(), "hi many\n", "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn run_file() {
+        let (mut interpreter, _, stderr) = interpreter();
+        let result = interpreter.run_file("does/not/exist.lox");
+        assert_eq!(result.unwrap_err().exit_code(), 66);
+        assert!(stderr
+            .contents()
+            .starts_with("Failed to read file does/not/exist.lox"));
+    }
+
+    #[test]
+    fn natives() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (interpreter, stdout, stderr) = interpreter();
+            let mut interpreter = interpreter.with_backend(backend).with_name("test.lox");
+            interpreter.define_native(NativeFunction::new(
+                "twice",
+                1,
+                |arguments| match arguments[0] {
+                    Literal::Number(number) => Ok((number * 2.0).into()),
+                    _ => Err("twice takes a number.".to_string()),
+                },
+            ));
+            interpreter.run_source("print twice(21);").unwrap();
+            assert_eq!(stdout.contents(), "42\n", "{backend:?}");
+            assert_eq!(
+                interpreter.eval_expr("twice").unwrap().to_string(),
+                "<native fn twice>"
+            );
+
+            // the error a native returns is reported like any other
+            let result = interpreter.run_source("twice(\"a\");");
+            assert_eq!(result.unwrap_err().exit_code(), 70);
+            assert!(stderr
+                .contents()
+                .starts_with("error[E0200]: twice takes a number.\n --> test.lox:1:1\n"));
+
+            // natives replace what was there, even the built in ones
+            interpreter.define_native(NativeFunction::new("clock", 0, |_| Ok(1.0.into())));
+            assert_eq!(
+                interpreter.eval_expr("clock()").unwrap(),
+                Literal::Number(1.0)
+            );
+        }
+    }
+
+    /// Output that can't be written to
+    struct Closed;
+
+    impl Write for Closed {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn print_to_closed_output() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let stderr = CapturedOutput::new();
+            let mut interpreter = Interpreter::new()
+                .with_backend(backend)
+                .with_stdout(Closed)
+                .with_stderr(stderr.clone());
+            let result = interpreter.run_source("print 1;");
+            assert!(
+                matches!(&result, Err(InterpretError::Runtime(error)) if error.code == "E0209"),
+                "{backend:?}"
+            );
+            assert!(stderr
+                .contents()
+                .starts_with("error[E0209]: Couldn't write output: broken pipe\n"));
+        }
+    }
+
+    #[test]
+    fn stages() {
+        let (mut interpreter, stdout, stderr) = interpreter();
+        interpreter.tokenize("1 + @").unwrap_err();
+        assert_eq!(stdout.contents(), "NUMBER 1 1.0\nPLUS + null\nEOF  null\n");
+        assert!(stderr
+            .contents()
+            .starts_with("error[E0001]: Unexpected character: '@'\n"));
+
+        stdout.clear();
+        interpreter.parse_expression("(1 + 2) * -x").unwrap();
+        assert_eq!(stdout.contents(), "(* (group (+ 1 2)) (- x))\n");
+
+        stdout.clear();
+        let bytes = interpreter.compile("print 1 + 2;").unwrap();
+        assert!(interpreter
+            .disassemble("print 1 + 2;")
+            .unwrap()
+            .contains("OP_ADD"));
+        assert_eq!(stdout.contents(), "");
+        interpreter.run_bytecode(&bytes).unwrap();
+        assert_eq!(stdout.contents(), "3\n");
+    }
+}
@@ -0,0 +1,104 @@
+//! An interpreter for the Lox language from *Crafting Interpreters*. Programs
+//! run on a tree-walker by default, or on a bytecode VM.
+//!
+//! Most embedders only need [`Interpreter`], which runs source code, holds
+//! onto globals between runs and sends `print` output and diagnostics
+//! wherever it is told to:
+//!
+//! ```
+//! use jlox_rust::{CapturedOutput, Interpreter, Literal, NativeFunction};
+//!
+//! let output = CapturedOutput::new();
+//! let mut interpreter = Interpreter::new().with_stdout(output.clone());
+//!
+//! // values can be passed in and out through globals
+//! interpreter.set_global("name", "world");
+//! interpreter.define_native(NativeFunction::new("double", 1, |arguments| {
+//!     match arguments[0] {
+//!         Literal::Number(number) => Ok((number * 2.0).into()),
+//!         _ => Err("double takes a number.".to_string()),
+//!     }
+//! }));
+//!
+//! interpreter
+//!     .run_source("print \"hello ${name}\"; var answer = double(21);")
+//!     .unwrap();
+//! assert_eq!(output.contents(), "hello world\n");
+//! assert_eq!(interpreter.get_global("answer"), Some(Literal::Number(42.0)));
+//! assert_eq!(interpreter.eval_expr("answer / 2").unwrap(), Literal::Number(21.0));
+//! ```
+//!
+//! The front end is public too, for tools that want to stop part way:
+//! [`scanner::tokenize`] turns source into tokens, [`parser::Parser`] turns
+//! those into the AST in [`parser::expression`] and [`parser::statement`], and
+//! [`resolver::resolve`] works out where every variable lives. Each stage
+//! reports mistakes to a [`Context`] as [`diagnostic::Diagnostic`]s. Running
+//! the AST is left to the [`Interpreter`], [`Interpreter::compile`] and
+//! [`Interpreter::disassemble`] give the bytecode the VM would run.
+//!
+//! ```
+//! use jlox_rust::{
+//!     parser::{expression::ExprKind, statement::StmtKind, Parser},
+//!     resolver, scanner, Context,
+//! };
+//!
+//! let mut context = Context::new();
+//! let tokens = scanner::tokenize("{ var a = 1; print a + 2; }", &mut context);
+//! let mut program = Parser::new(tokens).parse().unwrap();
+//! resolver::resolve(&mut program, &mut context);
+//! assert!(context.errors.is_empty());
+//!
+//! let StmtKind::Block(block) = &program[0].kind else {
+//!     panic!("expected a block");
+//! };
+//! let StmtKind::Print(sum) = &block[1].kind else {
+//!     panic!("expected a print statement");
+//! };
+//! assert!(matches!(sum.kind, ExprKind::Binary { .. }));
+//! assert_eq!(sum.pretty_string(), "(+ a 2)");
+//! ```
+
+pub(crate) mod class;
+pub mod diagnostic;
+pub(crate) mod environment;
+pub(crate) mod error;
+pub(crate) mod function;
+pub(crate) mod gc;
+pub(crate) mod interpreter;
+pub(crate) mod literal;
+pub(crate) mod native;
+pub(crate) mod optimizer;
+pub mod parser;
+pub mod resolver;
+pub mod scanner;
+pub mod span;
+pub(crate) mod vm;
+
+use diagnostic::Diagnostic;
+use parser::ParseError;
+
+pub use gc::GcConfig;
+pub use interpreter::{Backend, CapturedOutput, InterpretError, Interpreter};
+pub use literal::Literal;
+pub use native::NativeFunction;
+
+/// Collects the errors found while scanning, parsing and resolving, so they
+/// can all be reported at once instead of stopping at the first.
+#[derive(Debug, Default)]
+pub struct Context {
+    pub errors: Vec<Diagnostic>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Context { errors: vec![] }
+    }
+
+    pub fn error(&mut self, diagnostic: Diagnostic) {
+        self.errors.push(diagnostic);
+    }
+
+    pub fn parse_error(&mut self, error: ParseError) {
+        self.error(error.into());
+    }
+}
@@ -1,27 +1,264 @@
-use std::fmt::Display;
+use crate::{
+    class::{Class, Instance},
+    function::Function,
+    gc,
+    native::NativeFunction,
+    vm::object::{self, BoundMethod, Closure},
+};
+use std::{cell::RefCell, cmp::Ordering, collections::BTreeMap, fmt::Display, rc::Rc};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Literal {
     Number(f64),
-    // TODO: make strings faster
-    String(String),
+    /// Shared, strings can't be changed so copies never have to be made
+    String(Rc<str>),
     True,
     False,
     Nil,
+    Function(Rc<Function>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    /// Shared, so changes made through one variable show up in every other
+    /// variable holding the same list
+    List(Rc<RefCell<Vec<Literal>>>),
+    /// Shared like lists, kept sorted by key so printing and iterating over
+    /// the keys always gives the same order
+    Map(Rc<RefCell<BTreeMap<MapKey, Literal>>>),
+    // functions, classes and instances created by the bytecode VM
+    Closure(Rc<Closure>),
+    BoundMethod(Rc<BoundMethod>),
+    VmClass(Rc<object::Class>),
+    VmInstance(Rc<object::Instance>),
+}
+
+/// The values that can be used as keys in a map
+#[derive(Debug, Clone)]
+pub enum MapKey {
+    False,
+    True,
+    Number(f64),
+    String(Rc<str>),
+}
+
+/// The lists and maps a value is inside of while it's being shown, one that
+/// contains itself runs into its own pointer again
+type Enclosing = Vec<*const ()>;
+
+/// The pairs of lists and maps being compared further up
+type Comparing = Vec<(*const (), *const ())>;
+
+impl PartialEq for Literal {
+    fn eq(&self, other: &Self) -> bool {
+        self.equals(other, &mut vec![])
+    }
 }
 
 impl Literal {
+    /// `==` for values inside the lists and maps in `comparing`
+    fn equals(&self, other: &Self, comparing: &mut Comparing) -> bool {
+        match (self, other) {
+            (Literal::Number(left), Literal::Number(right)) => left == right,
+            (Literal::String(left), Literal::String(right)) => left == right,
+            (Literal::True, Literal::True)
+            | (Literal::False, Literal::False)
+            | (Literal::Nil, Literal::Nil) => true,
+            // functions, classes and instances are only equal to themselves
+            (Literal::Function(left), Literal::Function(right)) => Rc::ptr_eq(left, right),
+            (Literal::Native(left), Literal::Native(right)) => Rc::ptr_eq(left, right),
+            (Literal::Class(left), Literal::Class(right)) => Rc::ptr_eq(left, right),
+            (Literal::Instance(left), Literal::Instance(right)) => Rc::ptr_eq(left, right),
+            (Literal::Closure(left), Literal::Closure(right)) => Rc::ptr_eq(left, right),
+            (Literal::BoundMethod(left), Literal::BoundMethod(right)) => Rc::ptr_eq(left, right),
+            (Literal::VmClass(left), Literal::VmClass(right)) => Rc::ptr_eq(left, right),
+            (Literal::VmInstance(left), Literal::VmInstance(right)) => Rc::ptr_eq(left, right),
+            // lists are equal when their elements are
+            (Literal::List(left), Literal::List(right)) => {
+                compare_shared(left, right, comparing, |left, right, comparing| {
+                    left.len() == right.len()
+                        && left
+                            .iter()
+                            .zip(right)
+                            .all(|(left, right)| left.equals(right, comparing))
+                })
+            }
+            (Literal::Map(left), Literal::Map(right)) => {
+                compare_shared(left, right, comparing, |left, right, comparing| {
+                    left.len() == right.len()
+                        && left
+                            .iter()
+                            .zip(right)
+                            .all(|((left_key, left), (right_key, right))| {
+                                left_key == right_key && left.equals(right, comparing)
+                            })
+                })
+            }
+            _ => false,
+        }
+    }
+
     pub fn truthy(&self) -> bool {
+        !matches!(self, Literal::False | Literal::Nil)
+    }
+
+    /// What the `type` native returns for the value
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Literal::Number(_) => "number",
+            Literal::String(_) => "string",
+            Literal::True | Literal::False => "bool",
+            Literal::Nil => "nil",
+            Literal::Function(_)
+            | Literal::Native(_)
+            | Literal::Closure(_)
+            | Literal::BoundMethod(_) => "function",
+            Literal::Class(_) | Literal::VmClass(_) => "class",
+            Literal::Instance(_) | Literal::VmInstance(_) => "instance",
+            Literal::List(_) => "list",
+            Literal::Map(_) => "map",
+        }
+    }
+
+    /// How the value is shown inside a list or map, strings are quoted so
+    /// `["a, b"]` doesn't look like `["a", "b"]`
+    pub fn repr(&self) -> String {
+        self.nested(&mut vec![])
+    }
+
+    /// [`Literal::repr`] for a value inside the lists and maps in `enclosing`,
+    /// which are shown as `[...]` or `{...}` if the value contains them again
+    fn nested(&self, enclosing: &mut Enclosing) -> String {
+        match self {
+            Literal::String(text) => format!("\"{text}\""),
+            Literal::List(elements) => show_list(elements, enclosing),
+            Literal::Map(entries) => show_map(entries, enclosing),
+            value => value.to_string(),
+        }
+    }
+}
+
+/// Compares two lists or maps with `equal`. A pair that's already being
+/// compared further up contains itself in the same place on both sides, so
+/// it's taken to be equal and only the rest of it can make a difference.
+fn compare_shared<T>(
+    left: &Rc<RefCell<T>>,
+    right: &Rc<RefCell<T>>,
+    comparing: &mut Comparing,
+    equal: impl FnOnce(&T, &T, &mut Comparing) -> bool,
+) -> bool {
+    let pair = (
+        Rc::as_ptr(left) as *const (),
+        Rc::as_ptr(right) as *const (),
+    );
+    if Rc::ptr_eq(left, right) || comparing.contains(&pair) {
+        return true;
+    }
+    comparing.push(pair);
+    let equal = equal(&left.borrow(), &right.borrow(), comparing);
+    comparing.pop();
+    equal
+}
+
+fn show_list(elements: &Rc<RefCell<Vec<Literal>>>, enclosing: &mut Enclosing) -> String {
+    let pointer = Rc::as_ptr(elements) as *const ();
+    if enclosing.contains(&pointer) {
+        return "[...]".to_string();
+    }
+    enclosing.push(pointer);
+    let shown: Vec<_> = elements
+        .borrow()
+        .iter()
+        .map(|element| element.nested(enclosing))
+        .collect();
+    enclosing.pop();
+    format!("[{}]", shown.join(", "))
+}
+
+fn show_map(entries: &Rc<RefCell<BTreeMap<MapKey, Literal>>>, enclosing: &mut Enclosing) -> String {
+    let pointer = Rc::as_ptr(entries) as *const ();
+    if enclosing.contains(&pointer) {
+        return "{...}".to_string();
+    }
+    enclosing.push(pointer);
+    let shown: Vec<_> = entries
+        .borrow()
+        .iter()
+        .map(|(key, value)| {
+            let key = Literal::from(key.clone()).repr();
+            format!("{key}: {}", value.nested(enclosing))
+        })
+        .collect();
+    enclosing.pop();
+    format!("{{{}}}", shown.join(", "))
+}
+
+impl MapKey {
+    /// Keys of different types never compare equal, this just decides which
+    /// comes first
+    fn rank(&self) -> u8 {
         match self {
-            Literal::False | Literal::Nil => false,
-            _ => true,
+            MapKey::False => 0,
+            MapKey::True => 1,
+            MapKey::Number(_) => 2,
+            MapKey::String(_) => 3,
+        }
+    }
+}
+
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (MapKey::Number(left), MapKey::Number(right)) => left.total_cmp(right),
+            (MapKey::String(left), MapKey::String(right)) => left.cmp(right),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MapKey {}
+
+impl TryFrom<Literal> for MapKey {
+    /// The value that can't be a key
+    type Error = Literal;
+
+    fn try_from(value: Literal) -> Result<Self, Self::Error> {
+        Ok(match value {
+            Literal::False => MapKey::False,
+            Literal::True => MapKey::True,
+            // -0 and 0 are the same key
+            Literal::Number(number) => MapKey::Number(number + 0.0),
+            Literal::String(text) => MapKey::String(text),
+            value => return Err(value),
+        })
+    }
+}
+
+impl From<MapKey> for Literal {
+    fn from(key: MapKey) -> Self {
+        match key {
+            MapKey::False => Literal::False,
+            MapKey::True => Literal::True,
+            MapKey::Number(number) => Literal::Number(number),
+            MapKey::String(text) => Literal::String(text),
         }
     }
 }
 
 impl From<f64> for Literal {
     fn from(value: f64) -> Self {
-        Literal::Number(value.into())
+        Literal::Number(value)
     }
 }
 impl From<String> for Literal {
@@ -30,6 +267,24 @@ impl From<String> for Literal {
     }
 }
 
+impl From<&str> for Literal {
+    fn from(value: &str) -> Self {
+        Literal::String(value.into())
+    }
+}
+
+impl From<Vec<Literal>> for Literal {
+    fn from(value: Vec<Literal>) -> Self {
+        Literal::List(gc::manage(RefCell::new(value)))
+    }
+}
+
+impl From<BTreeMap<MapKey, Literal>> for Literal {
+    fn from(value: BTreeMap<MapKey, Literal>) -> Self {
+        Literal::Map(gc::manage(RefCell::new(value)))
+    }
+}
+
 impl From<bool> for Literal {
     fn from(value: bool) -> Self {
         if value {
@@ -45,11 +300,23 @@ impl Display for Literal {
             f,
             "{}",
             match self {
-                Literal::String(value) => format!("{value}"),
+                Literal::String(value) => value.to_string(),
                 Literal::Number(num) => format!("{num}"),
                 Literal::True => "true".to_string(),
                 Literal::False => "false".to_string(),
                 Literal::Nil => "nil".to_string(),
+                Literal::Function(function) => format!("<fn {}>", function.name()),
+                Literal::Native(native) => format!("<native fn {}>", native.name),
+                Literal::Class(class) => class.name().to_string(),
+                Literal::Instance(instance) => format!("{} instance", instance.class().name()),
+                Literal::Closure(closure) => format!("<fn {}>", closure.function.display_name()),
+                Literal::BoundMethod(bound) => {
+                    format!("<fn {}>", bound.method.function.display_name())
+                }
+                Literal::VmClass(class) => class.name.clone(),
+                Literal::VmInstance(instance) => format!("{} instance", instance.class.name),
+                Literal::List(elements) => show_list(elements, &mut vec![]),
+                Literal::Map(entries) => show_map(entries, &mut vec![]),
             }
         )
     }
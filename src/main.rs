@@ -1,119 +1,210 @@
-mod environment;
-mod literal;
-mod parser;
-mod scanner;
 use std::env;
 use std::fs;
 use std::io;
 use std::io::Write;
+use std::thread;
 
-use environment::Environment;
+use std::path::{Path, PathBuf};
+
+use jlox_rust::{parser::Parser, scanner, Backend, Context, GcConfig, Interpreter};
 
 // TODO: use clap
 
-struct Context {
-    errors: Vec<(String, u64)>,
+/// What diagnostics call the source typed into the REPL
+const REPL_NAME: &str = "<repl>";
+
+/// The tree-walker recurses on the native stack for every call, this is
+/// enough for as many as it allows even in a debug build
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// The flags given alongside the command
+#[derive(Default)]
+struct Options {
+    backend: Backend,
+    optimize: bool,
+    gc_stress: bool,
 }
 
-impl Context {
-    fn new() -> Self {
-        Context { errors: vec![] }
+impl Options {
+    /// `None` if there's a flag that doesn't exist
+    fn parse(flags: &[String]) -> Option<Options> {
+        let mut options = Options::default();
+        for flag in flags {
+            match flag.as_str() {
+                "--backend=tree-walker" => options.backend = Backend::TreeWalker,
+                "--backend=vm" => options.backend = Backend::Vm,
+                "-O" => options.optimize = true,
+                "--gc-stress" => options.gc_stress = true,
+                _ => return None,
+            }
+        }
+        Some(options)
     }
 
-    fn error(&mut self, message: &str, line: u64) {
-        self.errors.push((message.to_string(), line));
+    /// An interpreter set up the way the flags ask
+    fn interpreter(&self) -> Interpreter {
+        let gc = if self.gc_stress {
+            GcConfig::stress()
+        } else {
+            GcConfig::default()
+        };
+        Interpreter::new()
+            .with_backend(self.backend)
+            .with_optimizer(self.optimize)
+            .with_gc(gc)
     }
+}
 
-    fn print_errors(&self) {
-        for (message, line) in &self.errors {
-            eprintln!("[line {line}] Error: {}", message);
+/// What a line typed into the REPL is run as, the `;` after a statement can be
+/// left off
+fn repl_source(line: &str) -> String {
+    let line = line.trim_end();
+    if missing_semicolon(line) {
+        format!("{line};")
+    } else {
+        line.to_string()
+    }
+}
+
+/// Whether the only thing wrong with `line` is that its last statement needs a
+/// `;`. Anything else is run as typed so the errors point at what's there.
+fn missing_semicolon(line: &str) -> bool {
+    let mut context = Context::new();
+    let tokens = scanner::tokenize(line, &mut context);
+    match Parser::new(tokens).parse() {
+        Ok(_) => false,
+        Err(errors) => {
+            context.errors.is_empty()
+                && matches!(&errors[..], [error]
+                    if error.message.starts_with("expected ';'") && error.span.start == line.len())
         }
     }
 }
 
 fn main() {
-    let mut context = Context::new();
-    let mut env = Environment::new();
-    let args: Vec<String> = env::args().collect();
+    let cli = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run)
+        .expect("the interpreter's thread can be started");
+    // the panic has already been reported
+    if cli.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn run() {
+    let (flags, args): (Vec<String>, Vec<String>) =
+        env::args().partition(|arg| arg.starts_with('-'));
+    let Some(options) = Options::parse(&flags) else {
+        eprintln!(
+            "Usage: {} [--backend=tree-walker|vm] [-O] [--gc-stress] [<command> <filename>]",
+            args[0]
+        );
+        std::process::exit(64);
+    };
 
     // REPL
     if args.len() == 1 {
+        let mut interpreter = options.interpreter().with_name(REPL_NAME);
         let mut buffer = String::new();
         loop {
             print!(">>>");
             io::stdout().flush().expect("CONSOLE FLUSH ERROR");
-            std::io::stdin()
+            let read = std::io::stdin()
                 .read_line(&mut buffer)
                 .expect("If input cant be read we should panic");
-            buffer.push(';');
-            let tokens = scanner::tokenize(&buffer, &mut context);
-            context.print_errors();
-            let mut parser = parser::Parser::new(tokens);
-            let program = parser.parse();
-            for statement in program {
-                statement.execute(&mut env, &mut std::io::stdout());
+            if read == 0 {
+                return;
             }
+            // errors have already been reported and only matter for the line
+            // they were made on
+            let _ = interpreter.run_source(&repl_source(&buffer));
             buffer.clear();
         }
     }
 
     if args.len() < 3 {
-        eprintln!("Usage: {} <command> <filename>", args[0]);
+        eprintln!("Usage: {} <command> <filename> [<output>]", args[0]);
         return;
     }
 
     let command = &args[1];
     let filename = &args[2];
 
-    let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-        eprintln!("Failed to read file {}", filename);
-        String::new()
-    });
-    match command.as_str() {
-        "tokenize" => {
-            // You can use print statements as follows for debugging, they'll be visible when running tests.
-
-            let tokens = scanner::tokenize(&file_contents, &mut context);
-            context.print_errors();
-            for token in tokens {
-                println!(
-                    "{} {} {}",
-                    token.token_type(),
-                    token.lexeme(),
-                    token.literal()
-                )
-            }
-        }
-        "parse" => {
-            let tokens = scanner::tokenize(&file_contents, &mut context);
-            context.print_errors();
-            let mut parser = parser::Parser::new(tokens);
-            let parsed = parser.expression();
-            println!("{}", parsed.pretty_string());
-        }
-        "evaluate" => {
-            let tokens = scanner::tokenize(&file_contents, &mut context);
-            context.print_errors();
-            let mut parser = parser::Parser::new(tokens);
-            let parsed = parser.expression();
-            println!("{}", parsed.evaluate(&mut env))
+    // `run` reads the file itself
+    if command == "run" {
+        if let Err(error) = options.interpreter().run_file(filename) {
+            std::process::exit(error.exit_code());
         }
+        return;
+    }
 
-        "run" => {
-            let tokens = scanner::tokenize(&file_contents, &mut context);
-            context.print_errors();
-            let mut parser = parser::Parser::new(tokens);
-            let program = parser.parse();
-            for statement in program {
-                statement.execute(&mut env, &mut std::io::stdout());
-            }
+    // the same message and exit code as `run` gives, and nothing is written
+    let file_contents = fs::read_to_string(filename).unwrap_or_else(|error| {
+        eprintln!("Failed to read file {filename}: {error}");
+        std::process::exit(66);
+    });
+    let mut interpreter = options.interpreter().with_name(filename.as_str());
+    let result = match command.as_str() {
+        "tokenize" => interpreter.tokenize(&file_contents),
+        "parse" => interpreter.parse_expression(&file_contents),
+        "compile" => {
+            // `compile a.lox` writes `a.loxc` unless told where to
+            let output = args
+                .get(3)
+                .map_or_else(|| Path::new(filename).with_extension("loxc"), PathBuf::from);
+            interpreter.compile(&file_contents).map(|bytes| {
+                if let Err(error) = fs::write(&output, bytes) {
+                    eprintln!("Failed to write file {}: {error}", output.display());
+                    std::process::exit(74);
+                }
+            })
         }
+        "disassemble" => interpreter
+            .disassemble(&file_contents)
+            .map(|listing| print!("{listing}")),
+        "evaluate" => interpreter
+            .eval_expr(&file_contents)
+            .map(|value| println!("{value}")),
 
         _ => {
             panic!("Unknown command: {}", command);
         }
+    };
+    if let Err(error) = result {
+        std::process::exit(error.exit_code());
     }
-    if !context.errors.is_empty() {
-        std::process::exit(65);
+}
+
+#[cfg(test)]
+mod test {
+    use super::repl_source;
+    use jlox_rust::{CapturedOutput, Interpreter};
+
+    #[test]
+    fn repl_lines() {
+        let output = CapturedOutput::new();
+        let mut interpreter = Interpreter::new()
+            .with_stdout(output.clone())
+            .with_stderr(CapturedOutput::new());
+        for line in [
+            "print 1;\n",
+            "print 2\n",
+            "var a = 3\n",
+            "fun f() { print a; }\n",
+            "f() ;  \n",
+            "\n",
+            "if (a > 2) { print 4; }\n",
+            "var m = {\"a\": 5}\n",
+            "print m[\"a\"]\n",
+            "print {}\n",
+            "{ print 6; }\n",
+        ] {
+            interpreter.run_source(&repl_source(line)).unwrap();
+        }
+        assert_eq!(output.contents(), "1\n2\n3\n4\n5\n{}\n6\n");
+        // the errors in a line that's wrong some other way point at what was typed
+        assert_eq!(repl_source("print (1\n"), "print (1");
+        assert_eq!(repl_source("print 1 +\n"), "print 1 +");
     }
 }
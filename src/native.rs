@@ -0,0 +1,126 @@
+mod math;
+pub mod string;
+
+use crate::{environment::Environment, literal::Literal};
+use std::{
+    fmt::Debug,
+    io::BufRead,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What a native function is implemented as, failures are reported as a
+/// message that the caller turns into a runtime error pointing at the call.
+pub type NativeFn = dyn Fn(&[Literal]) -> Result<Literal, String>;
+
+/// A function implemented in Rust that Lox code calls like any other function
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: impl Into<String>,
+        arity: usize,
+        function: impl Fn(&[Literal]) -> Result<Literal, String> + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            arity,
+            function: Box::new(function),
+        }
+    }
+
+    pub fn call(&self, arguments: &[Literal]) -> Result<Literal, String> {
+        (self.function)(arguments)
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Defines the natives every program starts out with
+pub fn define_globals(environment: &mut Environment) {
+    let natives = [
+        NativeFunction::new("clock", 0, clock),
+        NativeFunction::new("str", 1, |arguments| Ok(arguments[0].to_string().into())),
+        NativeFunction::new("num", 1, num),
+        NativeFunction::new("len", 1, len),
+        NativeFunction::new("keys", 1, keys),
+        NativeFunction::new("type", 1, |arguments| {
+            Ok(arguments[0].type_name().to_string().into())
+        }),
+        NativeFunction::new("input", 0, input),
+        NativeFunction::new("fromCharCode", 1, string::from_char_code),
+    ];
+    for native in natives {
+        environment.define_native(native);
+    }
+    environment.insert("math".to_string(), math::namespace());
+}
+
+/// Seconds since the unix epoch, for timing things
+fn clock(_: &[Literal]) -> Result<Literal, String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|error| error.to_string())?;
+    Ok(now.as_secs_f64().into())
+}
+
+/// Parses a string into a number, numbers are passed through unchanged
+fn num(arguments: &[Literal]) -> Result<Literal, String> {
+    match &arguments[0] {
+        Literal::Number(number) => Ok(Literal::Number(*number)),
+        Literal::String(text) => text
+            .trim()
+            .parse::<f64>()
+            .map(Literal::Number)
+            .map_err(|_| format!("Can't convert \"{text}\" to a number.")),
+        value => Err(format!("Can't convert {} to a number.", value.type_name())),
+    }
+}
+
+/// Number of characters in a string, elements in a list or entries in a map
+fn len(arguments: &[Literal]) -> Result<Literal, String> {
+    Ok(match &arguments[0] {
+        Literal::String(text) => text.chars().count() as f64,
+        Literal::List(elements) => elements.borrow().len() as f64,
+        Literal::Map(entries) => entries.borrow().len() as f64,
+        _ => return Err("Can only take the length of strings, lists and maps.".to_string()),
+    }
+    .into())
+}
+
+/// The keys of a map as a list, in sorted order
+fn keys(arguments: &[Literal]) -> Result<Literal, String> {
+    let Literal::Map(entries) = &arguments[0] else {
+        return Err("Can only get the keys of a map.".to_string());
+    };
+    Ok(entries
+        .borrow()
+        .keys()
+        .cloned()
+        .map(Literal::from)
+        .collect::<Vec<_>>()
+        .into())
+}
+
+/// A line from stdin without the line ending, or nil once stdin is closed
+fn input(_: &[Literal]) -> Result<Literal, String> {
+    let mut line = String::new();
+    let read = std::io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .map_err(|error| format!("Couldn't read input: {error}."))?;
+    if read == 0 {
+        return Ok(Literal::Nil);
+    }
+    Ok(line.trim_end_matches(['\n', '\r']).to_string().into())
+}
@@ -0,0 +1,98 @@
+use super::NativeFunction;
+use crate::{
+    class::{Class, Instance},
+    gc,
+    literal::Literal,
+};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The `math` global, an instance whose fields are the math natives so they
+/// are called like `math.sqrt(2)`
+pub fn namespace() -> Literal {
+    let math = Instance::new(gc::manage(Class::new(
+        "Math".to_string(),
+        None,
+        HashMap::new(),
+    )));
+
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_nanos() as u64);
+    let rng = Rc::new(Cell::new(scramble(seed)));
+
+    let natives = [
+        unary("sqrt", f64::sqrt),
+        unary("floor", f64::floor),
+        unary("ceil", f64::ceil),
+        unary("abs", f64::abs),
+        unary("sin", f64::sin),
+        unary("cos", f64::cos),
+        binary("min", f64::min),
+        binary("max", f64::max),
+        binary("pow", f64::powf),
+        NativeFunction::new("random", 0, {
+            let rng = rng.clone();
+            move |_| Ok(random(&rng).into())
+        }),
+        NativeFunction::new("seed", 1, move |arguments| {
+            let [seed] = numbers("seed", arguments)?;
+            rng.set(scramble(seed.to_bits()));
+            Ok(Literal::Nil)
+        }),
+    ];
+    for native in natives {
+        math.set(native.name.clone(), Literal::Native(native.into()));
+    }
+    Literal::Instance(gc::manage(math))
+}
+
+fn unary(name: &'static str, function: fn(f64) -> f64) -> NativeFunction {
+    NativeFunction::new(name, 1, move |arguments| {
+        let [x] = numbers(name, arguments)?;
+        Ok(function(x).into())
+    })
+}
+
+fn binary(name: &'static str, function: fn(f64, f64) -> f64) -> NativeFunction {
+    NativeFunction::new(name, 2, move |arguments| {
+        let [x, y] = numbers(name, arguments)?;
+        Ok(function(x, y).into())
+    })
+}
+
+/// The arguments as numbers, the arity has already been checked by the caller
+fn numbers<const N: usize>(name: &str, arguments: &[Literal]) -> Result<[f64; N], String> {
+    let mut numbers = [0.0; N];
+    for (number, argument) in numbers.iter_mut().zip(arguments) {
+        let Literal::Number(argument) = argument else {
+            return Err(format!("Arguments to math.{name} must be numbers."));
+        };
+        *number = *argument;
+    }
+    Ok(numbers)
+}
+
+/// Spreads the bits of a seed out (splitmix64) so similar seeds give very
+/// different sequences, and never returns the 0 state xorshift gets stuck on
+fn scramble(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)).max(1)
+}
+
+/// Next number in [0, 1) from a xorshift64* generator
+fn random(state: &Cell<u64>) -> f64 {
+    let mut x = state.get();
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    state.set(x);
+    // the top 53 bits fill an f64's mantissa exactly
+    (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,138 @@
+use super::NativeFunction;
+use crate::literal::Literal;
+use std::rc::Rc;
+
+type Method = fn(&str, &[Literal]) -> Result<Literal, String>;
+
+/// The method called `name` bound to `text`, so `"a b".split(" ")` calls
+/// `split` with `text` set to `"a b"`. `None` if strings have no such method.
+/// The method shares `text` rather than copying it.
+pub fn method(text: Rc<str>, name: &str) -> Option<NativeFunction> {
+    let (arity, method): (usize, Method) = match name {
+        "len" => (0, |text, _| Ok((text.chars().count() as f64).into())),
+        "slice" => (2, slice),
+        "indexOf" => (1, index_of),
+        "contains" => (1, |text, arguments| {
+            Ok(text.contains(string("contains", &arguments[0])?).into())
+        }),
+        "split" => (1, split),
+        "join" => (1, join),
+        "trim" => (0, |text, _| Ok(text.trim().to_string().into())),
+        "upper" => (0, |text, _| Ok(text.to_uppercase().into())),
+        "lower" => (0, |text, _| Ok(text.to_lowercase().into())),
+        "replace" => (2, |text, arguments| {
+            let from = string("replace", &arguments[0])?;
+            let to = string("replace", &arguments[1])?;
+            Ok(text.replace(from, to).into())
+        }),
+        "charCodeAt" => (1, char_code_at),
+        _ => return None,
+    };
+    Some(NativeFunction::new(name, arity, move |arguments| {
+        method(&text, arguments)
+    }))
+}
+
+/// The string made of the single character with the given code point, the
+/// opposite of `charCodeAt`
+pub fn from_char_code(arguments: &[Literal]) -> Result<Literal, String> {
+    let Literal::Number(code) = arguments[0] else {
+        return Err("Argument to fromCharCode must be a number.".to_string());
+    };
+    if code.fract() != 0.0 || code < 0.0 || code > u32::MAX as f64 {
+        return Err(format!("{code} isn't a valid character code."));
+    }
+    char::from_u32(code as u32)
+        .map(|character| character.to_string().into())
+        .ok_or_else(|| format!("{code} isn't a valid character code."))
+}
+
+/// The characters from `start` up to but not including `end`
+fn slice(text: &str, arguments: &[Literal]) -> Result<Literal, String> {
+    let len = text.chars().count();
+    let start = char_index(&arguments[0], len, true)?;
+    let end = char_index(&arguments[1], len, true)?;
+    if start > end {
+        return Err(format!("Slice start {start} is after its end {end}."));
+    }
+    Ok(text
+        .chars()
+        .skip(start)
+        .take(end - start)
+        .collect::<String>()
+        .into())
+}
+
+/// Character index of the first occurrence of the argument, or -1
+fn index_of(text: &str, arguments: &[Literal]) -> Result<Literal, String> {
+    let needle = string("indexOf", &arguments[0])?;
+    Ok(match text.find(needle) {
+        Some(byte) => text[..byte].chars().count() as f64,
+        None => -1.0,
+    }
+    .into())
+}
+
+/// Splits on every occurrence of the separator, an empty separator splits the
+/// string into its characters
+fn split(text: &str, arguments: &[Literal]) -> Result<Literal, String> {
+    let separator = string("split", &arguments[0])?;
+    let parts: Vec<Literal> = if separator.is_empty() {
+        text.chars()
+            .map(|character| character.to_string().into())
+            .collect()
+    } else {
+        text.split(separator)
+            .map(|part| part.to_string().into())
+            .collect()
+    };
+    Ok(parts.into())
+}
+
+/// The elements of a list with the string between each of them, elements
+/// that aren't strings are converted the same way `str` does
+fn join(text: &str, arguments: &[Literal]) -> Result<Literal, String> {
+    let Literal::List(elements) = &arguments[0] else {
+        return Err("Argument to join must be a list.".to_string());
+    };
+    Ok(elements
+        .borrow()
+        .iter()
+        .map(Literal::to_string)
+        .collect::<Vec<_>>()
+        .join(text)
+        .into())
+}
+
+fn char_code_at(text: &str, arguments: &[Literal]) -> Result<Literal, String> {
+    let len = text.chars().count();
+    let index = char_index(&arguments[0], len, false)?;
+    let character = text.chars().nth(index).expect("index was checked above");
+    Ok((character as u32 as f64).into())
+}
+
+fn string<'a>(method: &str, argument: &'a Literal) -> Result<&'a str, String> {
+    match argument {
+        Literal::String(text) => Ok(text),
+        _ => Err(format!("Argument to {method} must be a string.")),
+    }
+}
+
+/// Checks a character index is a whole number inside a string of length
+/// `len`, `past_end` also allows the index just after the last character
+fn char_index(index: &Literal, len: usize, past_end: bool) -> Result<usize, String> {
+    let limit = if past_end { len + 1 } else { len };
+    match *index {
+        Literal::Number(index) if index.fract() != 0.0 => {
+            Err(format!("String index must be a whole number, got {index}."))
+        }
+        Literal::Number(index) if index < 0.0 => {
+            Err(format!("String index can't be negative, got {index}."))
+        }
+        Literal::Number(index) if index >= limit as f64 => Err(format!(
+            "String index {index} is out of range for a string of length {len}."
+        )),
+        Literal::Number(index) => Ok(index as usize),
+        _ => Err("String index must be a number.".to_string()),
+    }
+}
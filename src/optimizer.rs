@@ -0,0 +1,341 @@
+//! An optional pass over the resolved program that does work ahead of time:
+//! operators on constants are folded into the constant they produce, and `if`
+//! and `while` statements whose condition is a constant lose the branches
+//! that can never run. Folding uses the same rules as running the program, so
+//! anything that would fail at runtime, like `-"str"`, is left to fail there.
+
+use crate::{
+    literal::Literal,
+    parser::{
+        expression::{binary, unary, Expr, ExprKind},
+        statement::{FunctionDecl, Stmt, StmtKind},
+    },
+    scanner::Token,
+    span::Span,
+};
+use std::rc::Rc;
+
+/// Optimizes a resolved program. Only code that can never run is removed,
+/// so variables keep the scopes the resolver found for them.
+pub fn optimize(program: Vec<Stmt>) -> Vec<Stmt> {
+    statements(program)
+}
+
+/// Folds the constant parts of a single expression
+pub fn optimize_expression(expr: Expr) -> Expr {
+    expression(expr)
+}
+
+fn statements(statements: Vec<Stmt>) -> Vec<Stmt> {
+    statements.into_iter().filter_map(statement).collect()
+}
+
+/// `None` if the statement can never do anything
+fn statement(statement: Stmt) -> Option<Stmt> {
+    let Stmt { kind, span } = statement;
+    let kind = match kind {
+        StmtKind::Block(body) => StmtKind::Block(statements(body)),
+        StmtKind::Class {
+            name,
+            superclass,
+            mut methods,
+        } => {
+            methods.iter_mut().for_each(function);
+            StmtKind::Class {
+                name,
+                superclass,
+                methods,
+            }
+        }
+        StmtKind::Expression(expr) => StmtKind::Expression(expression(expr)),
+        StmtKind::Function(mut declaration) => {
+            function(&mut declaration);
+            StmtKind::Function(declaration)
+        }
+        StmtKind::If {
+            condition,
+            then_stmt,
+            else_stmt,
+        } => {
+            let condition = expression(condition);
+            let then_stmt = self::statement(*then_stmt);
+            let else_stmt = else_stmt.and_then(|else_stmt| self::statement(*else_stmt));
+            match constant(&condition) {
+                Some(value) if value.truthy() => return then_stmt,
+                Some(_) => return else_stmt,
+                None => StmtKind::If {
+                    condition,
+                    then_stmt: Box::new(then_stmt.unwrap_or_else(|| empty(span))),
+                    else_stmt: else_stmt.map(Box::new),
+                },
+            }
+        }
+        StmtKind::Print(expr) => StmtKind::Print(expression(expr)),
+        StmtKind::Return(expr) => StmtKind::Return(expr.map(expression)),
+        StmtKind::Var(name, value) => StmtKind::Var(name, expression(value)),
+        StmtKind::While {
+            condition,
+            body,
+            increment,
+        } => {
+            let condition = expression(condition);
+            if constant(&condition).is_some_and(|value| !value.truthy()) {
+                return None;
+            }
+            let body = self::statement(*body).unwrap_or_else(|| empty(span));
+            StmtKind::While {
+                condition,
+                body: Box::new(body),
+                increment: increment.map(expression),
+            }
+        }
+        kind @ (StmtKind::Break | StmtKind::Continue) => kind,
+    };
+    Some(Stmt { kind, span })
+}
+
+fn function(declaration: &mut Rc<FunctionDecl>) {
+    let declaration =
+        Rc::get_mut(declaration).expect("declarations are only shared once the program is running");
+    declaration.body = statements(std::mem::take(&mut declaration.body));
+}
+
+/// Stands in for a removed statement where one is required, like the body of
+/// an `if` whose only statement was removed
+fn empty(span: Span) -> Stmt {
+    Stmt {
+        kind: StmtKind::Block(vec![]),
+        span,
+    }
+}
+
+/// The value of `expr` if it's known without running the program
+fn constant(expr: &Expr) -> Option<&Literal> {
+    match &expr.kind {
+        ExprKind::Literal(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn literal(value: Literal, span: Span) -> Expr {
+    Expr::new(ExprKind::Literal(value), span)
+}
+
+fn boxed(expr: Expr) -> Box<Expr> {
+    Box::new(expression(expr))
+}
+
+fn expression(expr: Expr) -> Expr {
+    let Expr { kind, span } = expr;
+    let kind = match kind {
+        ExprKind::Grouping(inner) => {
+            let inner = expression(*inner);
+            match constant(&inner) {
+                Some(value) => return literal(value.clone(), span),
+                None => ExprKind::Grouping(Box::new(inner)),
+            }
+        }
+        ExprKind::Unary { op, expr } => {
+            let operand = expression(*expr);
+            let folded = constant(&operand).and_then(|value| unary(&op, value.clone(), span).ok());
+            if let Some(value) = folded {
+                return literal(value, span);
+            }
+            ExprKind::Unary {
+                op,
+                expr: Box::new(operand),
+            }
+        }
+        ExprKind::Binary { left, op, right } => {
+            let left = expression(*left);
+            let right = expression(*right);
+            let folded = constant(&left)
+                .zip(constant(&right))
+                .and_then(|(a, b)| binary(&op, a.clone(), b.clone(), span).ok());
+            if let Some(value) = folded {
+                return literal(value, span);
+            }
+            ExprKind::Binary {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            }
+        }
+        ExprKind::Logical { left, op, right } => {
+            let left = expression(*left);
+            let right = expression(*right);
+            // `and` and `or` give back whichever operand decided them
+            if let Some(value) = constant(&left) {
+                let decided = match op {
+                    Token::Or => value.truthy(),
+                    _ => !value.truthy(),
+                };
+                return if decided { left } else { right };
+            }
+            ExprKind::Logical {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            }
+        }
+        ExprKind::Assign { name, value, depth } => ExprKind::Assign {
+            name,
+            value: boxed(*value),
+            depth,
+        },
+        ExprKind::Call { callee, arguments } => ExprKind::Call {
+            callee: boxed(*callee),
+            arguments: arguments.into_iter().map(expression).collect(),
+        },
+        ExprKind::Get { object, name } => ExprKind::Get {
+            object: boxed(*object),
+            name,
+        },
+        ExprKind::Set {
+            object,
+            name,
+            value,
+        } => ExprKind::Set {
+            object: boxed(*object),
+            name,
+            value: boxed(*value),
+        },
+        ExprKind::Interpolation(parts) => {
+            ExprKind::Interpolation(parts.into_iter().map(expression).collect())
+        }
+        ExprKind::List(elements) => ExprKind::List(elements.into_iter().map(expression).collect()),
+        ExprKind::Map(entries) => ExprKind::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (expression(key), expression(value)))
+                .collect(),
+        ),
+        ExprKind::Index { object, index } => ExprKind::Index {
+            object: boxed(*object),
+            index: boxed(*index),
+        },
+        ExprKind::SetIndex {
+            object,
+            index,
+            value,
+        } => ExprKind::SetIndex {
+            object: boxed(*object),
+            index: boxed(*index),
+            value: boxed(*value),
+        },
+        kind @ (ExprKind::Literal(_)
+        | ExprKind::This { .. }
+        | ExprKind::Variable { .. }
+        | ExprKind::Super { .. }) => kind,
+    };
+    Expr::new(kind, span)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{optimize, optimize_expression};
+    use crate::{
+        interpreter::{CapturedOutput, InterpretError, Interpreter},
+        parser::{
+            statement::{Stmt, StmtKind},
+            Parser,
+        },
+        resolver::resolve,
+        scanner::tokenize,
+        Backend, Context,
+    };
+
+    /// The lisp-style form of expression `src` once optimized
+    fn folded(src: &str) -> String {
+        let tokens = tokenize(src, &mut Context::new());
+        let expr = Parser::new(tokens).expression().unwrap();
+        optimize_expression(expr).pretty_string()
+    }
+
+    fn optimized(src: &str) -> Vec<Stmt> {
+        let mut context = Context::new();
+        let tokens = tokenize(src, &mut context);
+        let mut program = Parser::new(tokens).parse().unwrap();
+        resolve(&mut program, &mut context);
+        assert!(context.errors.is_empty());
+        optimize(program)
+    }
+
+    /// What `src` prints with the optimizer on, on both backends
+    fn run(src: &str) -> Result<String, InterpretError> {
+        let mut printed = vec![];
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let output = CapturedOutput::new();
+            Interpreter::new()
+                .with_backend(backend)
+                .with_optimizer(true)
+                .with_stdout(output.clone())
+                .with_stderr(CapturedOutput::new())
+                .run_source(src)?;
+            printed.push(output.contents());
+        }
+        assert_eq!(printed[0], printed[1], "the backends disagree");
+        Ok(printed.remove(0))
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_eq!(folded("(5 + 2) * -6"), "-42");
+        assert_eq!(folded("!(1 < 2) == false"), "true");
+        assert_eq!(folded("\"a\" + \"b\""), "ab");
+        assert_eq!(folded("2 ** 3 ** 2"), "512");
+        assert_eq!(folded("nil or 1 and \"x\""), "x");
+        assert_eq!(folded("false and f()"), "false");
+        assert_eq!(folded("true and f()"), "(call f)");
+        assert_eq!(folded("x + (1 + 2)"), "(+ x 3)");
+    }
+
+    #[test]
+    fn leaves_runtime_errors_in_place() {
+        assert_eq!(folded("-\"str\""), "(- str)");
+        assert_eq!(folded("1 + nil"), "(+ 1 nil)");
+        assert_eq!(folded("1 ~/ (1 - 1)"), "(~/ 1 0)");
+
+        let Err(InterpretError::Runtime(error)) = run("print 1;\nprint -\"str\";") else {
+            panic!("expected a runtime error");
+        };
+        assert_eq!(error.message, "Operand must be a number.");
+        assert_eq!(error.span.line, 2);
+    }
+
+    #[test]
+    fn removes_dead_branches() {
+        let program = optimized(
+            "if (false) print 1;
+            if (1 > 2) print 2; else print 3;
+            if (nil) { print 4; }
+            while (false) print 5;
+            while (!true) {}
+            if (x) { if (false) print 6; }",
+        );
+        assert_eq!(program.len(), 2);
+        assert!(matches!(program[0].kind, StmtKind::Print(_)));
+        let StmtKind::If { then_stmt, .. } = &program[1].kind else {
+            panic!("expected the if with an unknown condition to stay");
+        };
+        assert!(matches!(&then_stmt.kind, StmtKind::Block(body) if body.is_empty()));
+    }
+
+    #[test]
+    fn behaves_like_the_unoptimized_program() {
+        assert_eq!(
+            run("var total = 0;
+                for (var i = 0; i < 5; i = i + 1) {
+                    if (true) total = total + (2 * 3);
+                    if (false or nil) total = 0;
+                }
+                while (false) total = 0;
+                fun f() { if (1 == 1) return \"folded\"; return \"not\"; }
+                print total;
+                print f();
+                print \"${1 + 2}\";")
+            .unwrap(),
+            "30\nfolded\n3\n"
+        );
+    }
+}
@@ -1,111 +1,335 @@
-mod expression;
-mod statement;
+pub mod expression;
+pub mod statement;
 #[cfg(test)]
 mod test;
 
-use self::{expression::Expr, statement::Stmt};
-use crate::{literal::Literal, scanner::Token};
+use self::{
+    expression::{Expr, ExprKind},
+    statement::{FunctionDecl, Stmt, StmtKind},
+};
+use crate::{
+    literal::Literal,
+    scanner::{SpannedToken, Token},
+    span::Span,
+};
 use std::iter::Peekable;
 
-//TODO: implement anyhow
+/// Upper bound on parameters and call arguments, same as jlox
+const MAX_ARGUMENTS: usize = 255;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    /// Diagnostic code, `E01xx` for syntax errors
+    pub code: &'static str,
+    pub help: Option<String>,
+}
+
+impl ParseError {
+    fn with_code(mut self, code: &'static str) -> Self {
+        self.code = code;
+        self
+    }
+
+    fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
 pub struct Parser<T>
 where
-    T: Iterator<Item = Token>,
+    T: Iterator<Item = SpannedToken>,
 {
     tokens: Peekable<T>,
-    line: u64,
-    //last_processed_stmt - for errors errors or something
+    /// Span of the last token consumed, nodes being built end here
+    previous: Span,
+    /// How many loops enclose the statement being parsed, not counting ones
+    /// outside the current function
+    loop_depth: usize,
+    /// Errors found so far, ones that don't leave the parser lost are added
+    /// here straight away instead of unwinding to `parse`
+    errors: Vec<ParseError>,
 }
 
+/// Bails out with an error pointing at the next token, or at `$span` if given,
+/// any trailing builder calls (eg. `.with_code(..)`) are applied to the error
 macro_rules! syntax_error {
     ($self: expr, $message: expr) => {
-        panic!("[line {}] Error: {}", $self.line, $message)
+        syntax_error!($self, $message, $self.peek_span())
+    };
+    ($self: expr, $message: expr, $span: expr $(, $builder: ident($($arg: expr),*))*) => {
+        return Err(ParseError {
+            message: std::string::String::from($message),
+            span: $span,
+            code: "E0100",
+            help: None,
+        }$(.$builder($($arg),*))*)
     };
 }
+
+/// Consumes the next token if it is `$expected`, otherwise bails out leaving
+/// the offending token in place so `synchronize` can look at it.
 macro_rules! assert_next_token {
     ($self: expr, $expected: expr) => {
-        match $self.tokens.next() {
-            Some(found) if found == $expected => {}
-            Some(found) if found != $expected => syntax_error!(
-                $self,
-                &format!(
-                    "$expected '{}' ({}), found '{}' ({})",
-                    $expected.lexeme(),
-                    $expected.token_type(),
-                    found.lexeme(),
-                    found.token_type()
-                )
-            ),
-            None => syntax_error!(
+        if !$self.advance_if_eq(&$expected) {
+            syntax_error!(
                 $self,
-                &format!(
-                    "$expected '{}' ({}), found EOF",
-                    $expected.lexeme(),
-                    $expected.token_type(),
-                )
-            ),
-            _ => unreachable!(),
+                match $self.peek() {
+                    Some(found) => format!(
+                        "expected '{}' ({}), found '{}' ({})",
+                        $expected.lexeme(),
+                        $expected.token_type(),
+                        found.lexeme(),
+                        found.token_type()
+                    ),
+                    None => format!(
+                        "expected '{}' ({}), found EOF",
+                        $expected.lexeme(),
+                        $expected.token_type(),
+                    ),
+                }
+            )
         }
     };
 }
 impl<T> Parser<T>
 where
-    T: Iterator<Item = Token>,
+    T: Iterator<Item = SpannedToken>,
 {
     pub fn new<U: IntoIterator<IntoIter = T>>(tokens: U) -> Self {
         Self {
             tokens: tokens.into_iter().peekable(),
-            line: 1,
+            previous: Span::default(),
+            loop_depth: 0,
+            errors: vec![],
         }
     }
 
+    fn peek(&mut self) -> Option<&Token> {
+        self.tokens.peek().map(|next| &next.token)
+    }
+
+    fn peek_span(&mut self) -> Span {
+        self.tokens.peek().map_or(self.previous, |next| next.span)
+    }
+
+    fn check(&mut self, expected: &Token) -> bool {
+        self.peek() == Some(expected)
+    }
+
+    fn advance_if(&mut self, predicate: impl FnOnce(&Token) -> bool) -> Option<Token> {
+        let next = self.tokens.next_if(|next| predicate(&next.token))?;
+        self.previous = next.span;
+        Some(next.token)
+    }
+
+    fn advance_if_eq(&mut self, expected: &Token) -> bool {
+        self.advance_if(|next| next == expected).is_some()
+    }
+
+    /// Wraps up a statement that started at `start` and ends at the last token consumed
+    fn stmt(&self, kind: StmtKind, start: Span) -> Stmt {
+        Stmt::new(kind, start.to(self.previous))
+    }
+
+    fn expr(&self, kind: ExprKind, start: Span) -> Expr {
+        Expr::new(kind, start.to(self.previous))
+    }
+
     // NOTE: maybe should take owned self?
-    pub fn parse(&mut self) -> Vec<Stmt> {
+    /// Parses the whole program, on a syntax error it skips ahead to the next
+    /// statement and carries on so every error in the file gets reported.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
         let mut statements = Vec::new();
-        while self.tokens.peek() != Some(&Token::EOF) {
-            statements.push(self.statement());
+        while self.peek().is_some_and(|token| token != &Token::EOF) {
+            statements.extend(self.declaration());
+        }
+        if self.errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(std::mem::take(&mut self.errors))
         }
-        statements
     }
 
-    pub fn statement(&mut self) -> Stmt {
-        let stmt = if let Some(Token::Var) = self.tokens.peek() {
-            self.declaration_statement()
-        } else {
-            self.non_declaration_statement()
-        };
+    /// A statement, or `None` if it has a syntax error. The error is recorded
+    /// and the parser skips to the next statement, so the rest of the block
+    /// it's in still gets checked.
+    fn declaration(&mut self) -> Option<Stmt> {
+        match self.statement() {
+            Ok(statement) => Some(statement),
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronize();
+                None
+            }
+        }
+    }
 
-        self.line += 1;
-        stmt
+    /// Discards tokens until we are probably at the start of a new statement,
+    /// ie. just after a `;` or right before a keyword that begins one.
+    fn synchronize(&mut self) {
+        // the token the error was found at goes first, even if it begins a
+        // statement, otherwise it would be parsed again and fail the same way
+        if !matches!(self.peek(), None | Some(Token::EOF))
+            && self
+                .tokens
+                .next()
+                .is_some_and(|next| next == Token::SemiColon)
+        {
+            return;
+        }
+        while let Some(token) = self.peek() {
+            match token {
+                Token::EOF
+                | Token::Class
+                | Token::Fun
+                | Token::Var
+                | Token::For
+                | Token::If
+                | Token::While
+                | Token::Print
+                | Token::Return => return,
+                Token::SemiColon => {
+                    self.tokens.next();
+                    return;
+                }
+                _ => {
+                    self.tokens.next();
+                }
+            }
+        }
+    }
+
+    pub fn statement(&mut self) -> Result<Stmt, ParseError> {
+        match self.peek() {
+            Some(Token::Var) => self.declaration_statement(),
+            Some(Token::Fun) => self.function_declaration(),
+            Some(Token::Class) => self.class_declaration(),
+            _ => self.non_declaration_statement(),
+        }
     }
 
-    fn declaration_statement(&mut self) -> Stmt {
+    fn identifier(&mut self, message: &str) -> Result<String, ParseError> {
+        match self.advance_if(|token| matches!(token, Token::Identifier(_))) {
+            Some(Token::Identifier(name)) => Ok(name),
+            _ => syntax_error!(self, message),
+        }
+    }
+
+    fn declaration_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek_span();
         assert_next_token!(self, Token::Var);
 
-        let Some(Token::Identifier(name)) = self.tokens.next() else {
-            panic!("Var should be followed by an identifier");
-        };
+        let name = self.identifier("expected variable name after 'var'")?;
 
-        let initializer = if self.tokens.next_if_eq(&Token::Equal).is_some() {
-            self.expression()
+        let initializer = if self.advance_if_eq(&Token::Equal) {
+            self.expression()?
         } else {
-            Literal::Nil.into()
+            Expr::new(ExprKind::Literal(Literal::Nil), self.previous)
         };
 
         assert_next_token!(self, Token::SemiColon);
-        return Stmt::Var(name, initializer);
+        Ok(self.stmt(StmtKind::Var(name, initializer), start))
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek_span();
+        assert_next_token!(self, Token::Class);
+        let name = self.identifier("expected class name")?;
+
+        let superclass = if self.advance_if_eq(&Token::Less) {
+            let superclass = self.identifier("expected superclass name")?;
+            if superclass == name {
+                syntax_error!(
+                    self,
+                    "a class can't inherit from itself",
+                    self.previous,
+                    with_code("E0103")
+                );
+            }
+            Some(Expr::new(
+                ExprKind::Variable {
+                    name: superclass,
+                    depth: None,
+                },
+                self.previous,
+            ))
+        } else {
+            None
+        };
+
+        assert_next_token!(self, Token::LeftBrace);
+        let mut methods = vec![];
+        while self.peek().is_some() && !self.check(&Token::RightBrace) {
+            methods.push(self.function()?.into());
+        }
+        assert_next_token!(self, Token::RightBrace);
+        Ok(self.stmt(
+            StmtKind::Class {
+                name,
+                superclass,
+                methods,
+            },
+            start,
+        ))
+    }
+
+    fn function_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek_span();
+        assert_next_token!(self, Token::Fun);
+        let function = self.function()?;
+        Ok(self.stmt(StmtKind::Function(function.into()), start))
+    }
+
+    /// Everything after the `fun` keyword: name, parameter list and body.
+    /// Methods in a class body are parsed with this directly.
+    fn function(&mut self) -> Result<FunctionDecl, ParseError> {
+        let name = self.identifier("expected function name")?;
+
+        assert_next_token!(self, Token::LeftParen);
+        let mut params = vec![];
+        if !self.check(&Token::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    syntax_error!(
+                        self,
+                        format!("can't have more than {MAX_ARGUMENTS} parameters"),
+                        self.peek_span(),
+                        with_code("E0102")
+                    );
+                }
+                params.push(self.identifier("expected parameter name")?);
+                if !self.advance_if_eq(&Token::Comma) {
+                    break;
+                }
+            }
+        }
+        assert_next_token!(self, Token::RightParen);
+
+        // a loop around the declaration can't be broken out of from inside it
+        let enclosing_loops = std::mem::take(&mut self.loop_depth);
+        let body = self.block_statements();
+        self.loop_depth = enclosing_loops;
+        Ok(FunctionDecl {
+            name,
+            params,
+            body: body?,
+        })
     }
 
     /// We use this because some places where we accept statements
     /// we only really allow non declaration statements
-    fn non_declaration_statement(&mut self) -> Stmt {
+    fn non_declaration_statement(&mut self) -> Result<Stmt, ParseError> {
         match self
-            .tokens
             .peek()
             .expect("statement function should not be called on empty tokenstream")
         {
             Token::If => self.if_statement(),
             Token::Print => self.print_statement(),
+            Token::Return => self.return_statement(),
+            Token::Break | Token::Continue => self.loop_control_statement(),
             Token::While => self.while_statement(),
             Token::For => self.for_statement(),
             Token::LeftBrace => self.block(),
@@ -113,11 +337,11 @@ where
         }
     }
 
-    fn for_statement(&mut self) -> Stmt {
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek_span();
         assert_next_token!(self, Token::For);
         assert_next_token!(self, Token::LeftParen);
         let initializer = match self
-            .tokens
             .peek()
             .expect("for statement must contain something after left paren")
         {
@@ -125,242 +349,479 @@ where
                 assert_next_token!(self, Token::SemiColon);
                 None
             }
-            Token::Var => Some(self.declaration_statement()),
-            _ => Some(self.expression_statement()),
+            Token::Var => Some(self.declaration_statement()?),
+            _ => Some(self.expression_statement()?),
         };
         let condition = if let &Token::SemiColon = self
-            .tokens
             .peek()
             .expect("for statement must contain something after initializer")
         {
             assert_next_token!(self, Token::SemiColon);
             None
         } else {
-            Some(self.expression_statement())
+            Some(self.expression_statement()?)
         };
         let increment = if let &Token::RightParen = self
-            .tokens
             .peek()
             .expect("for statement must contain something after condition")
         {
             assert_next_token!(self, Token::RightParen);
             None
         } else {
-            let inc = Some(self.expression());
+            let inc = Some(self.expression()?);
             assert_next_token!(self, Token::RightParen);
             inc
         };
 
-        let mut body = self.statement();
+        let body = self.loop_body()?;
 
-        if let Some(increment) = increment {
-            body = Stmt::Block(vec![body, Stmt::Expression(increment)]);
-        }
-        if let Some(condition) = condition {
-            let Stmt::Expression(condition) = condition else {
-                unreachable!(
-                    "impossible to reach as we create condition as an expression_statement"
-                );
-            };
-            body = Stmt::While(condition, Box::new(body))
-        }
+        // the desugared statements all point back at the whole for loop
+        let condition = match condition {
+            Some(condition) => {
+                let StmtKind::Expression(condition) = condition.kind else {
+                    unreachable!(
+                        "impossible to reach as we create condition as an expression_statement"
+                    );
+                };
+                condition
+            }
+            None => Expr::new(ExprKind::Literal(Literal::True), start),
+        };
+        let mut body = self.stmt(
+            StmtKind::While {
+                condition,
+                body: body.into(),
+                increment,
+            },
+            start,
+        );
 
         if let Some(initializer) = initializer {
-            body = Stmt::Block(vec![initializer, body]);
+            body = self.stmt(StmtKind::Block(vec![initializer, body]), start);
         }
-        body
+        Ok(body)
     }
-    fn while_statement(&mut self) -> Stmt {
+    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek_span();
         assert_next_token!(self, Token::While);
         assert_next_token!(self, Token::LeftParen);
-        let condition = self.expression();
+        let condition = self.expression()?;
         assert_next_token!(self, Token::RightParen);
 
+        let body = self.loop_body()?;
+        Ok(self.stmt(
+            StmtKind::While {
+                condition,
+                body: body.into(),
+                increment: None,
+            },
+            start,
+        ))
+    }
+
+    fn loop_body(&mut self) -> Result<Stmt, ParseError> {
+        self.loop_depth += 1;
         let body = self.statement();
-        Stmt::While(condition, body.into())
+        self.loop_depth -= 1;
+        body
+    }
+
+    fn loop_control_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek_span();
+        let (keyword, kind) = if self.advance_if_eq(&Token::Break) {
+            ("break", StmtKind::Break)
+        } else {
+            assert_next_token!(self, Token::Continue);
+            ("continue", StmtKind::Continue)
+        };
+        if self.loop_depth == 0 {
+            self.errors.push(ParseError {
+                message: format!("can't use '{keyword}' outside of a loop"),
+                span: start,
+                code: "E0110",
+                help: None,
+            });
+        }
+        assert_next_token!(self, Token::SemiColon);
+        Ok(self.stmt(kind, start))
     }
 
-    fn if_statement(&mut self) -> Stmt {
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek_span();
         assert_next_token!(self, Token::If);
         assert_next_token!(self, Token::LeftParen);
-        let condition = self.expression();
+        let condition = self.expression()?;
         assert_next_token!(self, Token::RightParen);
 
-        let then_stmt = self.statement().into();
-        let else_stmt = if self.tokens.next_if_eq(&Token::Else).is_some() {
-            Some(self.statement().into())
+        let then_stmt = self.statement()?.into();
+        let else_stmt = if self.advance_if_eq(&Token::Else) {
+            Some(self.statement()?.into())
         } else {
             None
         };
-        Stmt::If {
-            condition,
-            then_stmt,
-            else_stmt,
-        }
+        Ok(self.stmt(
+            StmtKind::If {
+                condition,
+                then_stmt,
+                else_stmt,
+            },
+            start,
+        ))
+    }
+    fn block(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek_span();
+        let statements = self.block_statements()?;
+        Ok(self.stmt(StmtKind::Block(statements), start))
     }
-    fn block(&mut self) -> Stmt {
+
+    fn block_statements(&mut self) -> Result<Vec<Stmt>, ParseError> {
         assert_next_token!(self, Token::LeftBrace);
         let mut statements = vec![];
-        while self.tokens.peek().is_some() && self.tokens.peek() != Some(&Token::RightBrace) {
-            statements.push(self.statement())
+        while self
+            .peek()
+            .is_some_and(|token| token != &Token::RightBrace && token != &Token::EOF)
+        {
+            statements.extend(self.declaration());
         }
         assert_next_token!(self, Token::RightBrace);
-        Stmt::Block(statements)
+        Ok(statements)
     }
 
-    fn print_statement(&mut self) -> Stmt {
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek_span();
+        assert_next_token!(self, Token::Return);
+        let value = if self.check(&Token::SemiColon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        assert_next_token!(self, Token::SemiColon);
+        Ok(self.stmt(StmtKind::Return(value), start))
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek_span();
         assert_next_token!(self, Token::Print);
-        let stmt = Stmt::Print(self.expression());
+        let kind = StmtKind::Print(self.expression()?);
         assert_next_token!(self, Token::SemiColon);
-        stmt
+        Ok(self.stmt(kind, start))
     }
 
-    fn expression_statement(&mut self) -> Stmt {
-        let stmt = Stmt::Expression(self.expression());
+    fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.peek_span();
+        let kind = StmtKind::Expression(self.expression()?);
         assert_next_token!(self, Token::SemiColon);
-        stmt
+        Ok(self.stmt(kind, start))
     }
 
-    pub fn expression(&mut self) -> Expr {
+    pub fn expression(&mut self) -> Result<Expr, ParseError> {
         self.assignment()
     }
 
-    fn assignment(&mut self) -> Expr {
-        let expr = self.or();
-        if self.tokens.next_if_eq(&Token::Equal).is_some() {
-            let value = self.assignment();
-            if let Expr::Variable(name) = expr {
-                return Expr::Assign {
+    fn assignment(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.or()?;
+        if self.advance_if_eq(&Token::Equal) {
+            let value = self.assignment()?;
+            let kind = match expr.kind {
+                ExprKind::Variable { name, .. } => ExprKind::Assign {
                     name,
                     value: Box::new(value),
-                };
-            } else {
-                syntax_error!(self, "invalid assigment target");
-            }
+                    depth: None,
+                },
+                ExprKind::Get { object, name } => ExprKind::Set {
+                    object,
+                    name,
+                    value: Box::new(value),
+                },
+                ExprKind::Index { object, index } => ExprKind::SetIndex {
+                    object,
+                    index,
+                    value: Box::new(value),
+                },
+                _ => syntax_error!(
+                    self,
+                    "invalid assigment target",
+                    expr.span,
+                    with_code("E0101"),
+                    with_help("only variables and fields can be assigned to")
+                ),
+            };
+            return Ok(self.expr(kind, expr.span));
         }
-        expr
+        Ok(expr)
     }
 
-    fn or(&mut self) -> Expr {
-        let mut expr = self.and();
-        while let Some(op) = self.tokens.next_if(|t| t == &Token::Or) {
-            let right = self.and();
-            expr = Expr::Logical {
-                left: expr.into(),
-                op,
-                right: right.into(),
-            };
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
+        while let Some(op) = self.advance_if(|t| t == &Token::Or) {
+            let right = self.and()?;
+            let start = expr.span;
+            expr = self.expr(
+                ExprKind::Logical {
+                    left: expr.into(),
+                    op,
+                    right: right.into(),
+                },
+                start,
+            );
         }
-        expr
+        Ok(expr)
     }
 
-    fn and(&mut self) -> Expr {
-        let mut expr = self.equality();
-        while let Some(op) = self.tokens.next_if(|t| t == &Token::And) {
-            let right = self.equality();
-            expr = Expr::Logical {
-                left: expr.into(),
-                op,
-                right: right.into(),
-            };
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.equality()?;
+        while let Some(op) = self.advance_if(|t| t == &Token::And) {
+            let right = self.equality()?;
+            let start = expr.span;
+            expr = self.expr(
+                ExprKind::Logical {
+                    left: expr.into(),
+                    op,
+                    right: right.into(),
+                },
+                start,
+            );
         }
-        expr
+        Ok(expr)
     }
 
-    fn equality(&mut self) -> Expr {
-        use Token::*;
-        let mut result = self.comparison();
-        while let Some(op) = self
-            .tokens
-            .next_if(|t| vec![BangEqual, EqualEqual].contains(t))
-        {
-            let right = self.comparison();
-            result = Expr::Binary {
-                left: result.into(),
-                op,
-                right: right.into(),
-            };
+    /// Builds a left associative chain of binary expressions out of operands
+    /// parsed by `operand`, joined by any of the `operators`.
+    fn binary(
+        &mut self,
+        operators: &[Token],
+        operand: fn(&mut Self) -> Result<Expr, ParseError>,
+    ) -> Result<Expr, ParseError> {
+        let mut result = operand(self)?;
+        while let Some(op) = self.advance_if(|t| operators.contains(t)) {
+            let right = operand(self)?;
+            let start = result.span;
+            result = self.expr(
+                ExprKind::Binary {
+                    left: result.into(),
+                    op,
+                    right: right.into(),
+                },
+                start,
+            );
         }
-        result
+        Ok(result)
     }
-    fn comparison(&mut self) -> Expr {
+
+    fn equality(&mut self) -> Result<Expr, ParseError> {
         use Token::*;
-        let mut result = self.term();
-        while let Some(op) = self
-            .tokens
-            .next_if(|t| vec![Greater, GreaterEqual, Less, LessEqual].contains(t))
-        {
-            let right = self.term();
-            result = Expr::Binary {
-                left: result.into(),
-                op,
-                right: right.into(),
-            };
-        }
-        result
+        self.binary(&[BangEqual, EqualEqual], Self::comparison)
+    }
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        use Token::*;
+        self.binary(&[Greater, GreaterEqual, Less, LessEqual], Self::term)
     }
 
-    fn term(&mut self) -> Expr {
+    fn term(&mut self) -> Result<Expr, ParseError> {
         use Token::*;
-        let mut result = self.factor();
-        while let Some(op) = self.tokens.next_if(|t| vec![Minus, Plus].contains(t)) {
-            let right = self.factor();
-            result = Expr::Binary {
-                left: result.into(),
-                op,
-                right: right.into(),
-            };
-        }
-        result
+        self.binary(&[Minus, Plus], Self::factor)
     }
 
-    fn factor(&mut self) -> Expr {
+    fn factor(&mut self) -> Result<Expr, ParseError> {
         use Token::*;
-        let mut result = self.unary();
-        while let Some(op) = self.tokens.next_if(|t| vec![Slash, Star].contains(t)) {
-            result = Expr::Binary {
-                left: result.into(),
-                op,
-                right: self.unary().into(),
-            };
-        }
-        result
+        self.binary(&[Slash, Star, Percent, TildeSlash], Self::unary)
     }
 
-    fn unary(&mut self) -> Expr {
+    fn unary(&mut self) -> Result<Expr, ParseError> {
         use Token::*;
-        if let Some(op) = self.tokens.next_if(|t| vec![Bang, Minus].contains(t)) {
-            let right = self.unary();
-            Expr::Unary {
-                op,
-                expr: right.into(),
-            }
+        let start = self.peek_span();
+        if let Some(op) = self.advance_if(|t| [Bang, Minus].contains(t)) {
+            let right = self.unary()?;
+            Ok(self.expr(
+                ExprKind::Unary {
+                    op,
+                    expr: right.into(),
+                },
+                start,
+            ))
         } else {
-            self.primary()
+            self.power()
         }
     }
 
-    fn primary(&mut self) -> Expr {
-        match self.tokens.next().unwrap() {
-            Token::True => Literal::True,
-            Token::False => Literal::False,
-            Token::Nil => Literal::Nil,
-            Token::Number(_, value) => Literal::Number(value),
-            Token::String(value) => Literal::String(value),
-            Token::LeftParen => {
-                let expr = self.expression();
-                assert_next_token!(self, Token::RightParen);
-                return Expr::Grouping(expr.into());
+    /// Binds tighter than unary minus on its left, so `-2 ** 2` is -4, and
+    /// groups to the right, so `2 ** 3 ** 2` is `2 ** 9`
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let start = self.peek_span();
+        let base = self.call()?;
+        if !self.advance_if_eq(&Token::StarStar) {
+            return Ok(base);
+        }
+        let exponent = self.unary()?;
+        Ok(self.expr(
+            ExprKind::Binary {
+                left: base.into(),
+                op: Token::StarStar,
+                right: exponent.into(),
+            },
+            start,
+        ))
+    }
+
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.advance_if_eq(&Token::LeftParen) {
+                expr = self.finish_call(expr)?;
+            } else if self.advance_if_eq(&Token::Dot) {
+                let name = self.identifier("expected property name after '.'")?;
+                let start = expr.span;
+                expr = self.expr(
+                    ExprKind::Get {
+                        object: expr.into(),
+                        name,
+                    },
+                    start,
+                );
+            } else if self.advance_if_eq(&Token::LeftBracket) {
+                let index = self.expression()?;
+                assert_next_token!(self, Token::RightBracket);
+                let start = expr.span;
+                expr = self.expr(
+                    ExprKind::Index {
+                        object: expr.into(),
+                        index: index.into(),
+                    },
+                    start,
+                );
+            } else {
+                break;
             }
-            Token::Identifier(name) => {
-                return Expr::Variable(name);
+        }
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+        let mut arguments = vec![];
+        if !self.check(&Token::RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    syntax_error!(
+                        self,
+                        format!("can't have more than {MAX_ARGUMENTS} arguments"),
+                        self.peek_span(),
+                        with_code("E0102")
+                    );
+                }
+                arguments.push(self.expression()?);
+                if !self.advance_if_eq(&Token::Comma) {
+                    break;
+                }
             }
-            invalid => syntax_error!(
-                self,
-                &format!("invalid primary token found {}", invalid.token_type())
-            ),
         }
-        .into()
+        assert_next_token!(self, Token::RightParen);
+        let start = callee.span;
+        Ok(self.expr(
+            ExprKind::Call {
+                callee: callee.into(),
+                arguments,
+            },
+            start,
+        ))
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        use Token::*;
+        let start = self.peek_span();
+        let Some(token) = self.advance_if(|t| {
+            matches!(
+                t,
+                True | False
+                    | Nil
+                    | Number(..)
+                    | String(_)
+                    | Interpolation(_)
+                    | LeftParen
+                    | LeftBracket
+                    | LeftBrace
+                    | Identifier(_)
+                    | This
+                    | Super
+            )
+        }) else {
+            let found = self.peek().map_or("EOF".to_string(), Token::token_type);
+            syntax_error!(self, format!("expected expression, found {found}"));
+        };
+
+        let kind = match token {
+            True => ExprKind::Literal(Literal::True),
+            False => ExprKind::Literal(Literal::False),
+            Nil => ExprKind::Literal(Literal::Nil),
+            Number(_, value) => ExprKind::Literal(Literal::Number(value)),
+            String(value) => ExprKind::Literal(value.into()),
+            Interpolation(text) => {
+                let mut parts = vec![];
+                // the scanner alternates text and embedded expressions, ending
+                // the string with a plain `String`
+                let mut piece = Interpolation(text);
+                loop {
+                    let (Interpolation(text) | String(text)) = &piece else {
+                        unreachable!("only string pieces are consumed below");
+                    };
+                    if !text.is_empty() {
+                        let kind = ExprKind::Literal(text.as_str().into());
+                        parts.push(self.expr(kind, self.previous));
+                    }
+                    if let String(_) = piece {
+                        break;
+                    }
+                    parts.push(self.expression()?);
+                    piece = match self.advance_if(|t| matches!(t, Interpolation(_) | String(_))) {
+                        Some(piece) => piece,
+                        None => syntax_error!(self, "expected '}' after interpolated expression"),
+                    };
+                }
+                ExprKind::Interpolation(parts)
+            }
+            LeftParen => {
+                let expr = self.expression()?;
+                assert_next_token!(self, RightParen);
+                ExprKind::Grouping(expr.into())
+            }
+            LeftBracket => {
+                let mut elements = vec![];
+                while !self.check(&RightBracket) {
+                    elements.push(self.expression()?);
+                    if !self.advance_if_eq(&Comma) {
+                        break;
+                    }
+                }
+                assert_next_token!(self, RightBracket);
+                ExprKind::List(elements)
+            }
+            // statements starting with `{` are blocks, so this is only
+            // reached from inside an expression
+            LeftBrace => {
+                let mut entries = vec![];
+                while !self.check(&RightBrace) {
+                    let key = self.expression()?;
+                    assert_next_token!(self, Colon);
+                    entries.push((key, self.expression()?));
+                    if !self.advance_if_eq(&Comma) {
+                        break;
+                    }
+                }
+                assert_next_token!(self, RightBrace);
+                ExprKind::Map(entries)
+            }
+            Identifier(name) => ExprKind::Variable { name, depth: None },
+            This => ExprKind::This { depth: None },
+            Super => {
+                assert_next_token!(self, Dot);
+                let method = self.identifier("expected superclass method name")?;
+                ExprKind::Super {
+                    method,
+                    depth: None,
+                }
+            }
+            _ => unreachable!("only tokens that can start an expression are consumed"),
+        };
+        Ok(self.expr(kind, start))
     }
 }
 
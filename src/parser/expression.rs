@@ -1,7 +1,24 @@
-use crate::{environment::Environment, literal::Literal, scanner::Token};
+use crate::{
+    environment::Environment,
+    error::RuntimeError,
+    function::CallGuard,
+    gc::{self, Rooted},
+    literal::{Literal, MapKey},
+    native,
+    scanner::Token,
+    span::Span,
+};
+use std::{collections::BTreeMap, io::Write};
 
 #[derive(Debug)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    /// The source code this expression was parsed from
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
     Grouping(Box<Expr>),
     Literal(Literal),
 
@@ -22,207 +39,630 @@ pub enum Expr {
         op: Token,
         right: Box<Expr>,
     },
-    // This(Token),
-    Variable(String),
+    This {
+        /// How many scopes out `this` is defined, filled in by the resolver
+        depth: Option<usize>,
+    },
+    Variable {
+        name: String,
+        /// How many scopes out the variable is defined, filled in by the
+        /// resolver, `None` for globals
+        depth: Option<usize>,
+    },
     Assign {
         name: String,
         value: Box<Expr>,
+        /// Same as for `Variable`
+        depth: Option<usize>,
+    },
+    Call {
+        callee: Box<Expr>,
+        arguments: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: String,
+    },
+    Set {
+        object: Box<Expr>,
+        name: String,
+        value: Box<Expr>,
+    },
+    /// A string with embedded `${}` expressions, the text between them is
+    /// kept as string literals
+    Interpolation(Vec<Expr>),
+    List(Vec<Expr>),
+    /// Key, value pairs in the order they were written
+    Map(Vec<(Expr, Expr)>),
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Super {
+        method: String,
+        /// How many scopes out `super` is defined, `this` is always one scope
+        /// further in
+        depth: Option<usize>,
     },
-    // Call {
-    //     callee: Box<Expr>,
-    //     paren: Token,
-    //     arguments: Vec<Box<Expr>>,
-    // },
-    // Get {
-    //     object: Box<Expr>,
-    //     name: Token,
-    // },
-    // Set {
-    //     object: Box<Expr>,
-    //     name: Token,
-    //     value: Box<Expr>,
-    // },
-    // Super {
-    //     keyword: Token,
-    //     method: Token,
-    // },
-}
-
-impl From<Literal> for Expr {
-    fn from(value: Literal) -> Self {
-        Expr::Literal(value)
-    }
 }
 
-impl From<Literal> for Option<Expr> {
-    fn from(value: Literal) -> Self {
-        Some(Expr::Literal(value))
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
     }
-}
 
-impl Expr {
     pub fn to_string_normal(&self) -> String {
-        match self {
-            Expr::Grouping(expr) => format!("({})", expr.to_string_normal()),
-            Expr::Literal(literal) => format!("{literal}"),
-            Expr::Unary { op, expr } => format!("{}{}", op.lexeme(), expr.to_string_normal()),
-            Expr::Binary { left, op, right } => format!(
+        match &self.kind {
+            ExprKind::Grouping(expr) => format!("({})", expr.to_string_normal()),
+            ExprKind::Literal(literal) => format!("{literal}"),
+            ExprKind::Unary { op, expr } => format!("{}{}", op.lexeme(), expr.to_string_normal()),
+            ExprKind::Binary { left, op, right } => format!(
                 "{} {} {}",
                 left.to_string_normal(),
                 op.lexeme(),
                 right.to_string_normal()
             ),
-            Expr::Variable(name) => name.clone(),
-            Expr::Assign { name, value } => format!("{name} = {}", value.to_string_normal()),
-            Expr::Logical { left, op, right } => todo!(),
+            ExprKind::Variable { name, .. } => name.clone(),
+            ExprKind::Assign { name, value, .. } => {
+                format!("{name} = {}", value.to_string_normal())
+            }
+            ExprKind::Logical { left, op, right } => format!(
+                "{} {} {}",
+                left.to_string_normal(),
+                op.lexeme(),
+                right.to_string_normal()
+            ),
+            ExprKind::Call { callee, arguments } => format!(
+                "{}({})",
+                callee.to_string_normal(),
+                arguments
+                    .iter()
+                    .map(Expr::to_string_normal)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ExprKind::Get { object, name } => format!("{}.{name}", object.to_string_normal()),
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => format!(
+                "{}.{name} = {}",
+                object.to_string_normal(),
+                value.to_string_normal()
+            ),
+            ExprKind::This { .. } => "this".to_string(),
+            ExprKind::Super { method, .. } => format!("super.{method}"),
+            ExprKind::Interpolation(parts) => format!(
+                "\"{}\"",
+                parts
+                    .iter()
+                    .map(|part| match &part.kind {
+                        ExprKind::Literal(Literal::String(text)) => text.to_string(),
+                        _ => format!("${{{}}}", part.to_string_normal()),
+                    })
+                    .collect::<String>()
+            ),
+            ExprKind::List(elements) => format!(
+                "[{}]",
+                elements
+                    .iter()
+                    .map(Expr::to_string_normal)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ExprKind::Map(entries) => format!(
+                "{{{}}}",
+                entries
+                    .iter()
+                    .map(|(key, value)| format!(
+                        "{}: {}",
+                        key.to_string_normal(),
+                        value.to_string_normal()
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ExprKind::Index { object, index } => {
+                format!(
+                    "{}[{}]",
+                    object.to_string_normal(),
+                    index.to_string_normal()
+                )
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => format!(
+                "{}[{}] = {}",
+                object.to_string_normal(),
+                index.to_string_normal(),
+                value.to_string_normal()
+            ),
         }
     }
     pub fn pretty_string(&self) -> String {
-        match self {
-            Expr::Grouping(expr) => format!("(group {})", expr.pretty_string()),
-            Expr::Literal(literal) => format!("{literal}"),
-            Expr::Unary { op, expr } => format!("({} {})", op.lexeme(), expr.pretty_string()),
-            Expr::Binary { left, op, right } => format!(
+        match &self.kind {
+            ExprKind::Grouping(expr) => format!("(group {})", expr.pretty_string()),
+            ExprKind::Literal(literal) => format!("{literal}"),
+            ExprKind::Unary { op, expr } => format!("({} {})", op.lexeme(), expr.pretty_string()),
+            ExprKind::Binary { left, op, right } => format!(
                 "({} {} {})",
                 op.lexeme(),
                 left.pretty_string(),
                 right.pretty_string()
             ),
-            Expr::Variable(_) => todo!(),
-            Expr::Assign { name, value } => todo!(),
-            Expr::Logical { left, op, right } => todo!(),
+            ExprKind::Variable { name, .. } => name.clone(),
+            ExprKind::Assign { name, value, .. } => format!("(= {name} {})", value.pretty_string()),
+            ExprKind::Logical { left, op, right } => format!(
+                "({} {} {})",
+                op.lexeme(),
+                left.pretty_string(),
+                right.pretty_string()
+            ),
+            ExprKind::Call { callee, arguments } => format!(
+                "(call {}{})",
+                callee.pretty_string(),
+                arguments
+                    .iter()
+                    .map(|argument| format!(" {}", argument.pretty_string()))
+                    .collect::<String>()
+            ),
+            ExprKind::Get { object, name } => format!("(. {} {name})", object.pretty_string()),
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => format!(
+                "(= (. {} {name}) {})",
+                object.pretty_string(),
+                value.pretty_string()
+            ),
+            ExprKind::This { .. } => "this".to_string(),
+            ExprKind::Super { method, .. } => format!("(super {method})"),
+            ExprKind::Interpolation(parts) => format!(
+                "(interpolate{})",
+                parts
+                    .iter()
+                    .map(|part| format!(" {}", part.pretty_string()))
+                    .collect::<String>()
+            ),
+            ExprKind::List(elements) => format!(
+                "(list{})",
+                elements
+                    .iter()
+                    .map(|element| format!(" {}", element.pretty_string()))
+                    .collect::<String>()
+            ),
+            ExprKind::Map(entries) => format!(
+                "(map{})",
+                entries
+                    .iter()
+                    .map(|(key, value)| format!(
+                        " ({} {})",
+                        key.pretty_string(),
+                        value.pretty_string()
+                    ))
+                    .collect::<String>()
+            ),
+            ExprKind::Index { object, index } => {
+                format!("([] {} {})", object.pretty_string(), index.pretty_string())
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => format!(
+                "(= ([] {} {}) {})",
+                object.pretty_string(),
+                index.pretty_string(),
+                value.pretty_string()
+            ),
         }
     }
-    pub fn evaluate(&self, environment: &mut Environment) -> Literal {
-        match self {
-            Expr::Grouping(expr) => expr.evaluate(environment),
-            Expr::Literal(literal) => literal.clone(),
-            Expr::Unary { op, expr } => match op {
-                Token::Bang => (!expr.evaluate(environment).truthy()).into(),
-                Token::Minus => {
-                    let Literal::Number(num) = expr.evaluate(environment) else {
-                        panic!("cannot take negative of non number");
-                    };
-                    (-num).into()
-                }
-                t => unreachable!(
-                    "invalid unary operator, should be unreachable (it was {}: {})",
-                    t.lexeme(),
-                    t.token_type()
-                ),
-            },
-            Expr::Binary { left, op, right } => {
-                match (left.evaluate(environment), right.evaluate(environment)) {
-                    (Literal::Number(left), Literal::Number(right)) => match op {
-                        Token::Plus => (left + right).into(),
-                        Token::Minus => (left - right).into(),
-                        Token::Star => (left * right).into(),
-                        Token::Slash => (left / right).into(),
-                        // relational
-                        Token::Less => (left < right).into(),
-                        Token::LessEqual => (left <= right).into(),
-                        Token::Greater => (left > right).into(),
-                        Token::GreaterEqual => (left >= right).into(),
-                        // Equality
-                        Token::EqualEqual => (left == right).into(),
-                        Token::BangEqual => (left != right).into(),
-                        op => {
-                            panic!("invalid operation {} on numbers", op.token_type())
-                        }
-                    },
-                    (Literal::String(left), Literal::String(right)) => match op {
-                        Token::Plus => format!("{left}{right}").into(),
-                        Token::EqualEqual => (left == right).into(),
-                        Token::BangEqual => (left != right).into(),
-                        op => {
-                            panic!("invalid operation {} on strings", op.token_type())
-                        }
-                    },
-
-                    (left, right) => match op {
-                        Token::EqualEqual => left == right,
-                        Token::BangEqual => left != right,
-                        op => {
-                            panic!(
-                                "invalid operation {} on {} and {}",
-                                op.token_type(),
-                                left.to_string(),
-                                right.to_string()
-                            )
-                        }
-                    }
-                    .into(),
-                }
+    pub(crate) fn evaluate<W: Write>(
+        &self,
+        environment: &mut Environment,
+        output: &mut W,
+    ) -> Result<Literal, RuntimeError> {
+        Ok(match &self.kind {
+            ExprKind::Grouping(expr) => expr.evaluate(environment, output)?,
+            ExprKind::Literal(literal) => literal.clone(),
+            ExprKind::Unary { op, expr } => {
+                unary(op, expr.evaluate(environment, output)?, self.span)?
+            }
+            ExprKind::Binary { left, op, right } => {
+                let left = left.evaluate(environment, output)?;
+                let _left = gc::root(&left);
+                binary(op, left, right.evaluate(environment, output)?, self.span)?
             }
-            Expr::Logical { left, op, right } => {
-                let left = left.evaluate(environment);
+            ExprKind::Logical { left, op, right } => {
+                let left = left.evaluate(environment, output)?;
                 match op {
                     Token::Or => {
                         if left.truthy() {
-                            return left;
+                            return Ok(left);
                         }
                     }
                     Token::And => {
                         if !left.truthy() {
-                            return left;
+                            return Ok(left);
                         }
                     }
-                    op => {
-                        panic!(
-                            "invalid operation {}, expected logical operation (and/or)",
-                            op.token_type(),
+                    op => unreachable!(
+                        "invalid operation {}, expected logical operation (and/or)",
+                        op.token_type(),
+                    ),
+                }
+                right.evaluate(environment, output)?
+            }
+            ExprKind::Variable { name, depth } => environment
+                .get(name, *depth)
+                .ok_or_else(|| undefined_variable(name, self.span))?,
+            ExprKind::Assign { name, value, depth } => {
+                let value = value.evaluate(environment, output)?;
+                if !environment.assign(name, *depth, value.clone()) {
+                    return Err(undefined_variable(name, self.span));
+                }
+                value
+            }
+            ExprKind::Call { callee, arguments } => {
+                let callee = callee.evaluate(environment, output)?;
+                let mut held = gc::root(&callee);
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| {
+                        let argument = argument.evaluate(environment, output)?;
+                        held.value(&argument);
+                        Ok(argument)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let arity = match &callee {
+                    Literal::Function(function) => function.arity(),
+                    Literal::Native(native) => native.arity,
+                    Literal::Class(class) => class.arity(),
+                    _ => {
+                        return Err(RuntimeError::new(
+                            "Can only call functions and classes.",
+                            self.span,
                         )
+                        .with_code("E0203"))
                     }
+                };
+                if arguments.len() != arity {
+                    return Err(wrong_arity(arity, arguments.len(), self.span));
+                }
+                let _call = CallGuard::enter(self.span)?;
+                match callee {
+                    Literal::Function(function) => function.call(arguments, output)?,
+                    Literal::Native(native) => native
+                        .call(&arguments)
+                        .map_err(|message| RuntimeError::new(message, self.span))?,
+                    Literal::Class(class) => class.instantiate(arguments, output)?,
+                    _ => unreachable!("checked when getting the arity"),
                 }
-                right.evaluate(environment)
-            }
-            // TODO: turn into a runtime error
-            Expr::Variable(name) => environment
-                .get(&name)
-                .expect("Variable not defined")
-                .clone(),
-            Expr::Assign { name, value } => {
-                let value = value.evaluate(environment);
-                let Some(var) = environment.get_mut(name) else {
-                    panic!("undefined variable \"{}\"", name);
+            }
+            ExprKind::Get { object, name } => {
+                property(object.evaluate(environment, output)?, name, self.span)?
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => {
+                let Literal::Instance(instance) = object.evaluate(environment, output)? else {
+                    return Err(RuntimeError::new("Only instances have fields.", self.span)
+                        .with_code("E0205"));
                 };
-                *var = value.clone();
+                let _instance = gc::root(&Literal::Instance(instance.clone()));
+                let value = value.evaluate(environment, output)?;
+                instance.set(name.clone(), value.clone());
+                value
+            }
+            ExprKind::This { depth } => environment.get("this", *depth).ok_or_else(|| {
+                RuntimeError::new("Can't use 'this' outside of a class.", self.span)
+            })?,
+            ExprKind::Super { method, depth } => {
+                let Some(Literal::Class(superclass)) = environment.get("super", *depth) else {
+                    return Err(RuntimeError::new(
+                        "Can't use 'super' in a class with no superclass.",
+                        self.span,
+                    ));
+                };
+                let instance = environment
+                    .get("this", depth.map(|depth| depth - 1))
+                    .expect("\"this\" is always defined inside methods");
+                let method = superclass.find_method(method).ok_or_else(|| {
+                    RuntimeError::new(format!("Undefined property '{method}'."), self.span)
+                        .with_code("E0205")
+                })?;
+                Literal::Function(gc::manage(method.bind(instance)))
+            }
+            ExprKind::Interpolation(parts) => {
+                let mut text = String::new();
+                for part in parts {
+                    text += &part.evaluate(environment, output)?.to_string();
+                }
+                text.into()
+            }
+            ExprKind::List(elements) => {
+                let mut held = Rooted::new();
+                elements
+                    .iter()
+                    .map(|element| {
+                        let element = element.evaluate(environment, output)?;
+                        held.value(&element);
+                        Ok(element)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into()
+            }
+            ExprKind::Map(entries) => {
+                let mut map = BTreeMap::new();
+                let mut held = Rooted::new();
+                for (key, value) in entries {
+                    let key_span = key.span;
+                    let key = key.evaluate(environment, output)?;
+                    let value = {
+                        let _key = gc::root(&key);
+                        value.evaluate(environment, output)?
+                    };
+                    held.value(&value);
+                    map.insert(map_key(key, key_span)?, value);
+                }
+                map.into()
+            }
+            ExprKind::Index { object, index } => {
+                let object = object.evaluate(environment, output)?;
+                let _object = gc::root(&object);
+                get_index(object, index.evaluate(environment, output)?, self.span)?
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                let object = object.evaluate(environment, output)?;
+                let mut held = gc::root(&object);
+                let index = index.evaluate(environment, output)?;
+                held.value(&index);
+                let value = value.evaluate(environment, output)?;
+                set_index(object, index, value.clone(), self.span)?;
                 value
             }
+        })
+    }
+}
+
+// The rules for operators, properties and indexing live outside `evaluate` so
+// the bytecode VM behaves exactly like the tree-walker.
+
+pub(crate) fn unary(op: &Token, operand: Literal, span: Span) -> Result<Literal, RuntimeError> {
+    Ok(match op {
+        Token::Bang => (!operand.truthy()).into(),
+        Token::Minus => {
+            let Literal::Number(num) = operand else {
+                return Err(RuntimeError::new("Operand must be a number.", span).with_code("E0204"));
+            };
+            (-num).into()
         }
+        t => unreachable!(
+            "invalid unary operator, should be unreachable (it was {}: {})",
+            t.lexeme(),
+            t.token_type()
+        ),
+    })
+}
+
+pub(crate) fn binary(
+    op: &Token,
+    left: Literal,
+    right: Literal,
+    span: Span,
+) -> Result<Literal, RuntimeError> {
+    Ok(match (left, right) {
+        (Literal::Number(left), Literal::Number(right)) => match op {
+            Token::Plus => (left + right).into(),
+            Token::Minus => (left - right).into(),
+            Token::Star => (left * right).into(),
+            Token::Slash => (left / right).into(),
+            // remainder and integer division both truncate towards
+            // zero, so `a == (a ~/ b) * b + a % b`
+            Token::Percent => (left % right).into(),
+            Token::TildeSlash => {
+                if right == 0.0 {
+                    return Err(
+                        RuntimeError::new("Integer division by zero.", span).with_code("E0207")
+                    );
+                }
+                (left / right).trunc().into()
+            }
+            Token::StarStar => left.powf(right).into(),
+            // relational
+            Token::Less => (left < right).into(),
+            Token::LessEqual => (left <= right).into(),
+            Token::Greater => (left > right).into(),
+            Token::GreaterEqual => (left >= right).into(),
+            // Equality
+            Token::EqualEqual => (left == right).into(),
+            Token::BangEqual => (left != right).into(),
+            op => unreachable!("invalid binary operator {}", op.token_type()),
+        },
+        (Literal::String(left), Literal::String(right)) => match op {
+            Token::Plus => format!("{left}{right}").into(),
+            Token::EqualEqual => (left == right).into(),
+            Token::BangEqual => (left != right).into(),
+            _ => {
+                return Err(RuntimeError::new("Operands must be numbers.", span).with_code("E0204"))
+            }
+        },
+
+        (left, right) => match op {
+            Token::EqualEqual => (left == right).into(),
+            Token::BangEqual => (left != right).into(),
+            Token::Plus => {
+                return Err(
+                    RuntimeError::new("Operands must be two numbers or two strings.", span)
+                        .with_code("E0204"),
+                )
+            }
+            _ => {
+                return Err(RuntimeError::new("Operands must be numbers.", span).with_code("E0204"))
+            }
+        },
+    })
+}
+
+pub(crate) fn undefined_variable(name: &str, span: Span) -> RuntimeError {
+    RuntimeError::new(format!("Undefined variable '{name}'."), span)
+        .with_code("E0201")
+        .with_help(format!("declare it first with `var {name};`"))
+}
+
+/// What a `print` statement does with its value
+pub(crate) fn print(
+    output: &mut impl Write,
+    value: &Literal,
+    span: Span,
+) -> Result<(), RuntimeError> {
+    writeln!(output, "{value}").map_err(|error| {
+        RuntimeError::new(format!("Couldn't write output: {error}"), span).with_code("E0209")
+    })
+}
+
+pub(crate) fn wrong_arity(arity: usize, got: usize, span: Span) -> RuntimeError {
+    RuntimeError::new(format!("Expected {arity} arguments but got {got}."), span).with_code("E0202")
+}
+
+/// `object.name` for the values that aren't specific to one backend
+pub(crate) fn property(object: Literal, name: &str, span: Span) -> Result<Literal, RuntimeError> {
+    match object {
+        Literal::Instance(instance) => instance.get(name).ok_or_else(|| {
+            RuntimeError::new(format!("Undefined property '{name}'."), span).with_code("E0205")
+        }),
+        Literal::String(text) => native::string::method(text, name)
+            .map(|method| Literal::Native(method.into()))
+            .ok_or_else(|| {
+                RuntimeError::new(format!("Strings have no method '{name}'."), span)
+                    .with_code("E0205")
+            }),
+        _ => Err(RuntimeError::new("Only instances have properties.", span).with_code("E0205")),
+    }
+}
+
+pub(crate) fn get_index(
+    object: Literal,
+    index: Literal,
+    span: Span,
+) -> Result<Literal, RuntimeError> {
+    match object {
+        Literal::List(elements) => {
+            let elements = elements.borrow();
+            Ok(elements[list_index(&index, elements.len(), span)?].clone())
+        }
+        Literal::Map(entries) => {
+            let key = map_key(index, span)?;
+            let value = entries.borrow().get(&key).cloned();
+            value.ok_or_else(|| {
+                RuntimeError::new(
+                    format!("Undefined key {}.", Literal::from(key).repr()),
+                    span,
+                )
+                .with_code("E0206")
+            })
+        }
+        _ => Err(RuntimeError::new("Only lists and maps can be indexed.", span).with_code("E0206")),
+    }
+}
+
+pub(crate) fn set_index(
+    object: Literal,
+    index: Literal,
+    value: Literal,
+    span: Span,
+) -> Result<(), RuntimeError> {
+    match object {
+        Literal::List(elements) => {
+            let mut elements = elements.borrow_mut();
+            let index = list_index(&index, elements.len(), span)?;
+            elements[index] = value;
+        }
+        Literal::Map(entries) => {
+            let key = map_key(index, span)?;
+            entries.borrow_mut().insert(key, value);
+        }
+        _ => {
+            return Err(
+                RuntimeError::new("Only lists and maps can be indexed.", span).with_code("E0206"),
+            )
+        }
+    }
+    Ok(())
+}
+
+/// Checks `index` can be used to index a list of length `len`
+fn list_index(index: &Literal, len: usize, span: Span) -> Result<usize, RuntimeError> {
+    let error = |message: String| Err(RuntimeError::new(message, span).with_code("E0206"));
+    match *index {
+        Literal::Number(index) if index.fract() != 0.0 => {
+            error(format!("List index must be a whole number, got {index}."))
+        }
+        Literal::Number(index) if index < 0.0 => {
+            error(format!("List index can't be negative, got {index}."))
+        }
+        Literal::Number(index) if index >= len as f64 => error(format!(
+            "List index {index} is out of range for a list of length {len}."
+        )),
+        Literal::Number(index) => Ok(index as usize),
+        _ => error("List index must be a number.".to_string()),
     }
 }
 
+pub(crate) fn map_key(key: Literal, span: Span) -> Result<MapKey, RuntimeError> {
+    MapKey::try_from(key).map_err(|key| {
+        RuntimeError::new(
+            format!("Map keys must be strings, numbers or booleans, got {key}."),
+            span,
+        )
+        .with_code("E0206")
+    })
+}
+
 #[cfg(test)]
 mod test {
 
-    use super::{Expr::*, Literal};
+    use super::{Expr, ExprKind::*, Literal};
+    use crate::{scanner::Token, span::Span};
+
+    fn node(kind: super::ExprKind) -> Box<Expr> {
+        Expr::new(kind, Span::default()).into()
+    }
 
     #[test]
     fn basic() {
         let expr_text = "(5 + 2) * -6 == 9";
-        let expr = Binary {
-            left: Binary {
-                left: Grouping(
-                    Binary {
-                        left: Literal(Literal::Number(5.0)).into(),
-                        op: crate::scanner::Token::Plus,
-                        right: Literal(Literal::Number(2.0)).into(),
-                    }
-                    .into(),
-                )
-                .into(),
-                op: crate::scanner::Token::Star,
-                right: Unary {
-                    op: crate::scanner::Token::Minus,
-                    expr: Literal(Literal::Number(6.0)).into(),
-                }
-                .into(),
-            }
-            .into(),
-            op: crate::scanner::Token::EqualEqual,
-            right: Literal(Literal::Number(9.0)).into(),
-        };
+        let expr = node(Binary {
+            left: node(Binary {
+                left: node(Grouping(node(Binary {
+                    left: node(Literal(Literal::Number(5.0))),
+                    op: Token::Plus,
+                    right: node(Literal(Literal::Number(2.0))),
+                }))),
+                op: Token::Star,
+                right: node(Unary {
+                    op: Token::Minus,
+                    expr: node(Literal(Literal::Number(6.0))),
+                }),
+            }),
+            op: Token::EqualEqual,
+            right: node(Literal(Literal::Number(9.0))),
+        });
 
         assert_eq!(expr.to_string_normal(), expr_text);
     }
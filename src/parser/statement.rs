@@ -1,63 +1,202 @@
-use super::expression::Expr;
-use crate::environment::Environment;
+use super::expression::{print, Expr};
+use crate::{
+    class::Class,
+    environment::Environment,
+    error::RuntimeError,
+    function::Function,
+    gc::{self, Rooted},
+    literal::Literal,
+    span::Span,
+};
+use std::{collections::HashMap, rc::Rc};
 
 #[derive(Debug)]
-pub enum Stmt {
-    //Class(Token , Box<Expr>.Variable superclass, Vec<Stmt.Function> methods)  ,
+pub struct Stmt {
+    pub kind: StmtKind,
+    /// The source code this statement was parsed from
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum StmtKind {
     Block(Vec<Stmt>),
+    Break,
+    Class {
+        name: String,
+        /// Always an `ExprKind::Variable`
+        superclass: Option<Expr>,
+        methods: Vec<Rc<FunctionDecl>>,
+    },
+    Continue,
     Expression(Expr),
-    // Function definition
-    // Function(Token, Vec<Token>, Vec<Stmt>),
+    Function(Rc<FunctionDecl>),
     If {
         condition: Expr,
         then_stmt: Box<Stmt>,
         else_stmt: Option<Box<Stmt>>,
     },
     Print(Expr),
-    // Return(Token, Expr),
+    /// `None` for a bare `return;`
+    Return(Option<Expr>),
     Var(String, Expr),
-    While(Expr, Box<Stmt>),
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+        /// Only set for desugared `for` loops, run after the body even when
+        /// it ends with `continue`
+        increment: Option<Expr>,
+    },
+}
+
+/// Shared between the `StmtKind::Function` node and every function value created from it
+#[derive(Debug)]
+pub struct FunctionDecl {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+}
+
+/// How control left a statement, `Return` has to unwind through enclosing
+/// blocks and loops until it reaches the function call, `Break` and
+/// `Continue` only as far as the closest loop.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Flow {
+    Normal,
+    Return(Literal),
+    Break,
+    Continue,
 }
 
 impl Stmt {
-    pub fn execute<W: std::io::Write>(&self, env: &mut Environment, output: &mut W) {
-        match self {
-            Stmt::Print(expr) => {
-                let text = expr.evaluate(env).to_string();
-                output
-                    .write(text.as_bytes())
-                    .and_then(|_| output.write(b"\n"))
-                    .expect("Write Error");
-            }
-            Stmt::Expression(expr) => {
-                expr.evaluate(env);
-            }
-            Stmt::Var(name, value) => {
-                let value = value.evaluate(env);
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    pub(crate) fn execute<W: std::io::Write>(
+        &self,
+        env: &mut Environment,
+        output: &mut W,
+    ) -> Result<Flow, RuntimeError> {
+        gc::safe_point(|roots| env.trace(roots));
+        match &self.kind {
+            StmtKind::Print(expr) => {
+                let value = expr.evaluate(env, output)?;
+                print(output, &value, self.span)?;
+            }
+            StmtKind::Expression(expr) => {
+                expr.evaluate(env, output)?;
+            }
+            StmtKind::Var(name, value) => {
+                let value = value.evaluate(env, output)?;
                 // idk if we need to do anything on redefinition
                 env.insert(name.to_string(), value);
             }
-            Stmt::Block(statements) => {
-                env.push_scope();
-                for statement in statements {
-                    statement.execute(env, output);
+            StmtKind::Function(declaration) => {
+                let function = Function::new(declaration.clone(), env.clone(), false);
+                env.insert(
+                    declaration.name.clone(),
+                    Literal::Function(gc::manage(function)),
+                );
+            }
+            StmtKind::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let superclass = match superclass {
+                    Some(superclass) => match superclass.evaluate(env, output)? {
+                        Literal::Class(class) => Some(class),
+                        _ => {
+                            return Err(RuntimeError::new(
+                                "Superclass must be a class.",
+                                superclass.span,
+                            )
+                            .with_code("E0204"));
+                        }
+                    },
+                    None => None,
+                };
+
+                // methods of a subclass close over a scope that defines `super`
+                let mut method_env = env.clone();
+                if let Some(superclass) = &superclass {
+                    method_env = env.enclosed();
+                    method_env.insert("super".to_string(), Literal::Class(superclass.clone()));
                 }
-                env.pop_scope();
+
+                let methods: HashMap<_, _> = methods
+                    .iter()
+                    .map(|method| {
+                        let function = Function::new(
+                            method.clone(),
+                            method_env.clone(),
+                            method.name == "init",
+                        );
+                        (method.name.clone(), gc::manage(function))
+                    })
+                    .collect();
+                let class = Class::new(name.clone(), superclass, methods);
+                env.insert(name.clone(), Literal::Class(gc::manage(class)));
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => value.evaluate(env, output)?,
+                    None => Literal::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            StmtKind::Block(statements) => {
+                return execute_block(statements, &mut env.enclosed(), output)
             }
-            Stmt::If {
+            StmtKind::If {
                 condition,
                 then_stmt,
                 else_stmt,
-            } => match (condition.evaluate(env).truthy(), else_stmt) {
-                (true, _) => then_stmt.execute(env, output),
-                (false, Some(else_stmt)) => else_stmt.execute(env, output),
-                (false, None) => {}
-            },
-            Stmt::While(condition, body) => {
-                while condition.evaluate(env).truthy() {
-                    body.execute(env, output)
+            } => {
+                return match (condition.evaluate(env, output)?.truthy(), else_stmt) {
+                    (true, _) => then_stmt.execute(env, output),
+                    (false, Some(else_stmt)) => else_stmt.execute(env, output),
+                    (false, None) => Ok(Flow::Normal),
                 }
             }
+            StmtKind::While {
+                condition,
+                body,
+                increment,
+            } => {
+                while condition.evaluate(env, output)?.truthy() {
+                    match body.execute(env, output)? {
+                        Flow::Break => break,
+                        Flow::Normal | Flow::Continue => {}
+                        flow @ Flow::Return(_) => return Ok(flow),
+                    }
+                    if let Some(increment) = increment {
+                        increment.evaluate(env, output)?;
+                    }
+                }
+            }
+            StmtKind::Break => return Ok(Flow::Break),
+            StmtKind::Continue => return Ok(Flow::Continue),
         };
+        Ok(Flow::Normal)
+    }
+}
+
+/// Runs statements one after another in `env`, stopping at the first one that
+/// returns, breaks, continues or fails.
+pub(crate) fn execute_block<W: std::io::Write>(
+    statements: &[Stmt],
+    env: &mut Environment,
+    output: &mut W,
+) -> Result<Flow, RuntimeError> {
+    // safe points inside a call only see the scopes of the function called
+    let mut scope = Rooted::new();
+    scope.trace(|roots| env.trace(roots));
+    for statement in statements {
+        let flow = statement.execute(env, output)?;
+        if flow != Flow::Normal {
+            return Ok(flow);
+        }
     }
+    Ok(Flow::Normal)
 }
@@ -1,21 +1,54 @@
 // TODO: Figure out how this works
 #[cfg(test)]
 use crate::{
-    scanner::{tokenize, Token},
+    environment::Environment,
+    error::RuntimeError,
+    resolver::resolve,
+    scanner::{tokenize, SpannedToken},
     Context,
 };
 
-use super::Parser;
+use super::{statement::Stmt, Parser};
 
-fn get_parser(src: &str) -> Parser<impl Iterator<Item = Token>> {
+fn get_parser(src: &str) -> Parser<impl Iterator<Item = SpannedToken>> {
     let mut context = Context::new();
     let tokens = tokenize(src, &mut context).into_iter().peekable();
     assert!(context.errors.is_empty());
     Parser::new(tokens)
 }
 
+/// Parses and resolves a program that should have no static errors
+fn get_program(src: &str) -> Vec<Stmt> {
+    let mut program = get_parser(src).parse().unwrap();
+    let mut context = Context::new();
+    resolve(&mut program, &mut context);
+    assert_eq!(context.errors, vec![]);
+    program
+}
+
+/// Runs a whole program and returns everything it printed
+fn execute(src: &str) -> Vec<u8> {
+    let mut environment = Environment::new();
+    let mut buffer = Vec::<u8>::new();
+    for statement in get_program(src) {
+        statement.execute(&mut environment, &mut buffer).unwrap();
+    }
+    buffer
+}
+
+/// Runs a program that is expected to fail at runtime
+fn runtime_error(src: &str) -> RuntimeError {
+    let mut environment = Environment::new();
+    for statement in get_program(src) {
+        if let Err(error) = statement.execute(&mut environment, &mut Vec::new()) {
+            return error;
+        }
+    }
+    panic!("program ran without errors")
+}
+
 fn utf8_to_string(buffer: &[u8]) -> Vec<&str> {
-    std::str::from_utf8(&buffer)
+    std::str::from_utf8(buffer)
         .expect("comes from a valid string, so it should be a valid string")
         .split('\n')
         .collect()
@@ -27,7 +60,10 @@ mod expressions {
     fn complex() {
         let expr_text = "(5+2)*-6 == 9";
         assert_eq!(
-            get_parser(expr_text).expression().to_string_normal(),
+            get_parser(expr_text)
+                .expression()
+                .unwrap()
+                .to_string_normal(),
             "(5 + 2) * -6 == 9"
         )
     }
@@ -36,7 +72,10 @@ mod expressions {
     fn equalities() {
         let expr_text = "(5==2) == -6 != 9";
         assert_eq!(
-            get_parser(expr_text).expression().to_string_normal(),
+            get_parser(expr_text)
+                .expression()
+                .unwrap()
+                .to_string_normal(),
             "(5 == 2) == -6 != 9"
         )
     }
@@ -45,7 +84,10 @@ mod expressions {
     fn literal() {
         let expr_text = "\"testing\"";
         assert_eq!(
-            get_parser(expr_text).expression().to_string_normal(),
+            get_parser(expr_text)
+                .expression()
+                .unwrap()
+                .to_string_normal(),
             "testing"
         )
     }
@@ -60,7 +102,9 @@ mod evaluate {
         assert_eq!(
             get_parser(expr_text)
                 .expression()
-                .evaluate(&mut Environment::new()),
+                .unwrap()
+                .evaluate(&mut Environment::new(), &mut Vec::new())
+                .unwrap(),
             Literal::False
         )
     }
@@ -70,7 +114,9 @@ mod evaluate {
         assert_eq!(
             get_parser(expr_text)
                 .expression()
-                .evaluate(&mut Environment::new()),
+                .unwrap()
+                .evaluate(&mut Environment::new(), &mut Vec::new())
+                .unwrap(),
             Literal::Number(-42.0)
         )
     }
@@ -81,7 +127,9 @@ mod evaluate {
         assert_eq!(
             get_parser(expr_text)
                 .expression()
-                .evaluate(&mut Environment::new()),
+                .unwrap()
+                .evaluate(&mut Environment::new(), &mut Vec::new())
+                .unwrap(),
             Literal::Number(1.0)
         )
     }
@@ -92,7 +140,9 @@ mod evaluate {
         assert_eq!(
             get_parser(expr_text)
                 .expression()
-                .evaluate(&mut Environment::new()),
+                .unwrap()
+                .evaluate(&mut Environment::new(), &mut Vec::new())
+                .unwrap(),
             Literal::True
         )
     }
@@ -103,14 +153,16 @@ mod evaluate {
         assert_eq!(
             get_parser(expr_text)
                 .expression()
-                .evaluate(&mut Environment::new()),
+                .unwrap()
+                .evaluate(&mut Environment::new(), &mut Vec::new())
+                .unwrap(),
             Literal::String("Hello, World!".into())
         )
     }
 }
 
 mod run {
-    use crate::{environment::Environment, parser::test::get_parser};
+    use crate::{environment::Environment, parser::test::get_program};
 
     #[test]
     fn declare() {
@@ -119,10 +171,10 @@ mod run {
                     print x;";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         assert_eq!(buffer, b"7\n");
@@ -135,10 +187,10 @@ mod run {
                     print x + y;";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         assert_eq!(buffer, b"12\n")
@@ -151,10 +203,10 @@ mod run {
                     print x;";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         assert_eq!(buffer, b"7\n")
@@ -172,10 +224,10 @@ mod run {
                     ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         assert_eq!(buffer, b"5\ntrue\n10\n")
@@ -183,44 +235,124 @@ mod run {
 }
 mod should_not_work {
 
-    use crate::{environment::Environment, parser::test::get_parser};
+    use crate::{
+        environment::Environment,
+        error::RuntimeError,
+        parser::{
+            statement::Flow,
+            test::{get_parser, get_program, runtime_error, utf8_to_string},
+            ParseError,
+        },
+        span::Span,
+    };
 
     #[test]
-    #[should_panic]
     fn basic() {
         let code = "
                 var x = 2
-                print x
+                print x;
                 x = x+1
                 ";
-        let mut environment = Environment::new();
-
-        let program = get_parser(code).parse();
-        let mut buffer = Vec::<u8>::new();
-        for statement in program {
-            statement.execute(&mut environment, &mut buffer);
-        }
+        let errors = get_parser(code).parse().unwrap_err();
+        let missing_semicolon = |found: &str, span: Span| ParseError {
+            message: format!("expected ';' (SEMICOLON), found {found}"),
+            span,
+            code: "E0100",
+            help: None,
+        };
+        assert_eq!(
+            errors,
+            vec![
+                missing_semicolon(
+                    "'print' (PRINT)",
+                    Span {
+                        start: 43,
+                        end: 48,
+                        line: 3,
+                        column: 17
+                    }
+                ),
+                missing_semicolon(
+                    "'' (EOF)",
+                    Span {
+                        start: 92,
+                        end: 92,
+                        line: 5,
+                        column: 17
+                    }
+                ),
+            ]
+        );
     }
 
     #[test]
-    #[should_panic]
     fn undefined() {
         let code = "
                 print(x);
                 ";
-        let mut environment = Environment::new();
+        assert_eq!(
+            runtime_error(code),
+            RuntimeError::new(
+                "Undefined variable 'x'.",
+                Span {
+                    start: 23,
+                    end: 24,
+                    line: 2,
+                    column: 23
+                }
+            )
+            .with_code("E0201")
+            .with_help("declare it first with `var x;`")
+        );
+    }
+
+    #[test]
+    fn type_errors() {
+        assert_eq!(
+            runtime_error("-\"str\";").message,
+            "Operand must be a number."
+        );
+        assert_eq!(
+            runtime_error("1 + \"str\";").message,
+            "Operands must be two numbers or two strings."
+        );
+        assert_eq!(
+            runtime_error("\"a\" < \"b\";").message,
+            "Operands must be numbers."
+        );
+        assert_eq!(
+            runtime_error("nil < 1;").message,
+            "Operands must be numbers."
+        );
+    }
 
-        let program = get_parser(code).parse();
+    #[test]
+    fn error_stops_execution() {
+        let code = "
+                print 1;
+                print -nil;
+                print 2;
+                ";
+        let mut environment = Environment::new();
         let mut buffer = Vec::<u8>::new();
-        for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+        let mut result = Ok(Flow::Normal);
+        for statement in get_program(code) {
+            result = statement.execute(&mut environment, &mut buffer);
+            if result.is_err() {
+                break;
+            }
         }
+        assert_eq!(utf8_to_string(&buffer), vec!["1", ""]);
+        let error = result.unwrap_err();
+        assert_eq!(error.message, "Operand must be a number.");
+        assert_eq!(&code[error.span.start..error.span.end], "-nil");
+        assert_eq!((error.span.line, error.span.column), (3, 23));
     }
 }
 mod block {
     use crate::{
         environment::Environment,
-        parser::test::{get_parser, utf8_to_string},
+        parser::test::{get_program, utf8_to_string},
     };
 
     #[test]
@@ -233,10 +365,10 @@ mod block {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         assert_eq!(utf8_to_string(&buffer), vec!["2", ""])
@@ -267,10 +399,10 @@ mod block {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
         let output = vec![
             "inner a", "outer b", "global c", //
@@ -294,10 +426,10 @@ mod block {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
         let output = vec!["1", "2", "2", ""];
         assert_eq!(utf8_to_string(&buffer), output)
@@ -307,7 +439,7 @@ mod block {
 mod control_flow {
     use crate::{
         environment::Environment,
-        parser::test::{get_parser, utf8_to_string},
+        parser::test::{get_program, utf8_to_string},
     };
 
     #[test]
@@ -324,10 +456,10 @@ mod control_flow {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         let output = vec![
@@ -344,10 +476,10 @@ mod control_flow {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         let output = vec!["hi", "yes", ""];
@@ -363,10 +495,10 @@ mod control_flow {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         let output = vec!["true", ""];
@@ -383,10 +515,10 @@ mod control_flow {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         let output = vec!["true", ""];
@@ -404,10 +536,10 @@ mod control_flow {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         let output = vec!["false", ""];
@@ -425,10 +557,10 @@ mod control_flow {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         let output = vec!["true", ""];
@@ -449,10 +581,10 @@ mod control_flow {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         let output = vec!["true then true", ""];
@@ -471,10 +603,10 @@ mod control_flow {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         let output = vec!["0", "1", "2", "3", "4", "5", "6", "7", "8", "9", ""];
@@ -491,13 +623,1295 @@ mod control_flow {
             ";
         let mut environment = Environment::new();
 
-        let program = get_parser(code).parse();
+        let program = get_program(code);
         let mut buffer = Vec::<u8>::new();
         for statement in program {
-            statement.execute(&mut environment, &mut buffer);
+            statement.execute(&mut environment, &mut buffer).unwrap();
         }
 
         let output = vec!["0", "1", "2", "3", "4", "5", "6", "7", "8", "9", ""];
         assert_eq!(utf8_to_string(&buffer), output)
     }
 }
+
+mod loop_control {
+    use crate::parser::test::{execute, get_parser, utf8_to_string};
+
+    #[test]
+    fn break_out_of_while() {
+        let code = "
+                var i = 0;
+                while (true) {
+                    if (i == 3) break;
+                    print i;
+                    i = i + 1;
+                }
+                print \"done\";
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["0", "1", "2", "done", ""]
+        )
+    }
+
+    #[test]
+    fn continue_runs_for_increment() {
+        let code = "
+                for (var i = 0; i < 5; i = i + 1) {
+                    if (i == 1 or i == 3) continue;
+                    print i;
+                }
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["0", "2", "4", ""])
+    }
+
+    #[test]
+    fn continue_in_while() {
+        let code = "
+                var i = 0;
+                while (i < 4) {
+                    i = i + 1;
+                    if (i == 2) continue;
+                    print i;
+                }
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["1", "3", "4", ""])
+    }
+
+    #[test]
+    fn only_leaves_innermost_loop() {
+        let code = "
+                for (var i = 0; i < 2; i = i + 1) {
+                    for (;;) {
+                        print i;
+                        break;
+                    }
+                }
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["0", "1", ""])
+    }
+
+    #[test]
+    fn return_inside_loop() {
+        let code = "
+                fun first_over(limit) {
+                    for (var i = 0; ; i = i + 1) {
+                        if (i > limit) return i;
+                    }
+                }
+                print first_over(5);
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["6", ""])
+    }
+
+    #[test]
+    fn outside_of_loop() {
+        let code = "
+                break;
+                while (true) {
+                    fun inner() { continue; }
+                    break;
+                }
+                if (true) continue;
+            ";
+        let errors = get_parser(code).parse().unwrap_err();
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.code, error.message.as_str(), error.span.line))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("E0110", "can't use 'break' outside of a loop", 2),
+                ("E0110", "can't use 'continue' outside of a loop", 4),
+                ("E0110", "can't use 'continue' outside of a loop", 7),
+            ]
+        );
+    }
+}
+
+mod functions {
+    use crate::parser::test::{execute, runtime_error, utf8_to_string};
+
+    #[test]
+    fn call() {
+        let code = "
+                fun add(a, b) {
+                    print a + b;
+                }
+                add(1, 2);
+                print add;
+            ";
+        let output = vec!["3", "<fn add>", ""];
+        assert_eq!(utf8_to_string(&execute(code)), output)
+    }
+
+    #[test]
+    fn implicit_nil_return() {
+        let code = "
+                fun nothing() {}
+                print nothing();
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["nil", ""])
+    }
+
+    #[test]
+    fn recursion() {
+        let code = "
+                fun fib(n) {
+                    if (n < 2) return n;
+                    return fib(n - 1) + fib(n - 2);
+                }
+                print fib(10);
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["55", ""])
+    }
+
+    #[test]
+    fn stack_overflow() {
+        // deep calls need more room than a test thread has by default
+        let (error, output) = std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(|| {
+                let error = runtime_error("fun f(n) { return 1 + f(n - 1); }\nf(0);");
+                // every call that overflowed has been unwound
+                (error, execute("fun g(n) { return n; } print g(1);"))
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(error.message, "Stack overflow.");
+        assert_eq!(error.code, "E0208");
+        assert_eq!(error.span.line, 1);
+        assert_eq!(utf8_to_string(&output), vec!["1", ""]);
+    }
+
+    #[test]
+    fn return_unwinds_loops_and_blocks() {
+        let code = "
+                fun first_above(limit) {
+                    for (var i = 0; i < 100; i = i + 1) {
+                        {
+                            if (i > limit) return i;
+                        }
+                    }
+                    return -1;
+                }
+                print first_above(4);
+                print first_above(200);
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["5", "-1", ""])
+    }
+
+    #[test]
+    fn locals_of_caller_are_hidden() {
+        let code = "
+                var x = \"global\";
+                fun show() {
+                    print x;
+                }
+                {
+                    var x = \"local\";
+                    show();
+                }
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["global", ""])
+    }
+
+    #[test]
+    fn wrong_arity() {
+        let code = "
+                fun one(a) {}
+                one(1, 2);
+            ";
+        assert_eq!(
+            runtime_error(code).message,
+            "Expected 1 arguments but got 2."
+        )
+    }
+
+    #[test]
+    fn call_non_function() {
+        assert_eq!(
+            runtime_error("\"not a function\"();").message,
+            "Can only call functions and classes."
+        )
+    }
+
+    #[test]
+    fn error_inside_call() {
+        let code = "
+                fun fails() {
+                    return 1 + nil;
+                }
+                fails();
+            ";
+        assert_eq!(
+            runtime_error(code).message,
+            "Operands must be two numbers or two strings."
+        )
+    }
+}
+
+mod closures {
+    use crate::parser::test::{execute, utf8_to_string};
+
+    #[test]
+    fn counter() {
+        let code = "
+                fun make_counter() {
+                    var i = 0;
+                    fun count() {
+                        i = i + 1;
+                        print i;
+                    }
+                    return count;
+                }
+                var counter = make_counter();
+                counter();
+                counter();
+                var other = make_counter();
+                other();
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["1", "2", "1", ""])
+    }
+
+    #[test]
+    fn outlives_block() {
+        let code = "
+                var get;
+                var set;
+                {
+                    var secret = \"before\";
+                    fun getter() { return secret; }
+                    fun setter(value) { secret = value; }
+                    get = getter;
+                    set = setter;
+                }
+                print get();
+                set(\"after\");
+                print get();
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["before", "after", ""])
+    }
+
+    #[test]
+    fn factory() {
+        let code = "
+                fun adder(n) {
+                    fun add(x) { return x + n; }
+                    return add;
+                }
+                var add2 = adder(2);
+                var add10 = adder(10);
+                print add2(1);
+                print add10(1);
+                print adder(3)(4);
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["3", "11", "7", ""])
+    }
+
+    #[test]
+    fn callback() {
+        let code = "
+                fun twice(f, x) { return f(f(x)); }
+                fun double(x) { return x * 2; }
+                print twice(double, 3);
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["12", ""])
+    }
+
+    #[test]
+    fn binds_to_the_variable_in_scope_when_declared() {
+        let code = "
+                var a = \"global\";
+                {
+                    fun show() { print a; }
+                    show();
+                    var a = \"block\";
+                    show();
+                    print a;
+                }
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["global", "global", "block", ""]
+        )
+    }
+}
+
+mod lists {
+    use crate::parser::test::{execute, get_parser, runtime_error, utf8_to_string};
+
+    #[test]
+    fn parse() {
+        let expr = get_parser("xs[0] = [1, [2], ys[i + 1]]")
+            .expression()
+            .unwrap();
+        assert_eq!(
+            expr.pretty_string(),
+            "(= ([] xs 0) (list 1 (list 2) ([] ys (+ i 1))))"
+        );
+    }
+
+    #[test]
+    fn literals_and_printing() {
+        let code = "
+                print [];
+                print [1, \"two\", nil, [true],];
+                var n = 2;
+                print [n, n * 2][1];
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["[]", "[1, \"two\", nil, [true]]", "4", ""]
+        )
+    }
+
+    #[test]
+    fn assignment() {
+        let code = "
+                var grid = [[0, 0], [0, 0]];
+                grid[1][0] = 5;
+                print grid;
+                var i = 0;
+                print grid[0][i] = 3;
+                print grid;
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["[[0, 0], [5, 0]]", "3", "[[3, 0], [5, 0]]", ""]
+        )
+    }
+
+    #[test]
+    fn shared_between_variables() {
+        let code = "
+                var a = [1, 2];
+                var b = a;
+                b[0] = 10;
+                print a;
+                fun clear(list) { list[1] = nil; }
+                clear(a);
+                print b;
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["[10, 2]", "[10, nil]", ""]
+        )
+    }
+
+    #[test]
+    fn equality() {
+        let code = "
+                print [1, [2, \"three\"]] == [1, [2, \"three\"]];
+                print [1, 2] == [2, 1];
+                print [1] != [1, 1];
+                print [] == nil;
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["true", "false", "true", "false", ""]
+        )
+    }
+
+    #[test]
+    fn containing_themselves() {
+        let code = "
+                var a = [1];
+                a[0] = a;
+                print a;
+                var b = [1, [2]];
+                b[1][0] = b;
+                print b;
+                print [a, a];
+                var c = [1];
+                c[0] = c;
+                print a == c;
+                print a == [a];
+                var d = [1, nil];
+                d[1] = d;
+                var e = [2, nil];
+                e[1] = e;
+                print d == e;
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec![
+                "[[...]]",
+                "[1, [[...]]]",
+                "[[[...]], [[...]]]",
+                "true",
+                "true",
+                "false",
+                ""
+            ]
+        )
+    }
+
+    #[test]
+    fn bad_indices() {
+        for (code, message) in [
+            ("[1, 2][-1];", "List index can't be negative, got -1."),
+            (
+                "[1, 2][2];",
+                "List index 2 is out of range for a list of length 2.",
+            ),
+            (
+                "var xs = []; xs[0] = 1;",
+                "List index 0 is out of range for a list of length 0.",
+            ),
+            (
+                "[1, 2][0.5];",
+                "List index must be a whole number, got 0.5.",
+            ),
+            ("[1, 2][\"0\"];", "List index must be a number."),
+            ("\"abc\"[0];", "Only lists and maps can be indexed."),
+        ] {
+            let error = runtime_error(code);
+            assert_eq!((error.message.as_str(), error.code), (message, "E0206"));
+        }
+    }
+}
+
+mod maps {
+    use crate::parser::test::{execute, get_parser, runtime_error, utf8_to_string};
+
+    #[test]
+    fn parse() {
+        let expr = get_parser("{\"a\": 1, 2: [x], true: {}}")
+            .expression()
+            .unwrap();
+        assert_eq!(
+            expr.pretty_string(),
+            "(map (a 1) (2 (list x)) (true (map)))"
+        );
+    }
+
+    #[test]
+    fn literals_and_printing() {
+        let code = "
+                print {};
+                print {\"b\": 2, \"a\": [1], 10: nil, -1: \"neg\", true: 1, false: 0};
+                {
+                    var m = {\"k\": {\"nested\": true}};
+                    print m[\"k\"][\"nested\"];
+                }
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec![
+                "{}",
+                "{false: 0, true: 1, -1: \"neg\", 10: nil, \"a\": [1], \"b\": 2}",
+                "true",
+                ""
+            ]
+        )
+    }
+
+    #[test]
+    fn assignment() {
+        let code = "
+                var scores = {\"ann\": 1};
+                scores[\"bob\"] = 2;
+                scores[\"ann\"] = scores[\"ann\"] + 10;
+                var alias = scores;
+                alias[0] = \"zero\";
+                print scores;
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["{0: \"zero\", \"ann\": 11, \"bob\": 2}", ""]
+        )
+    }
+
+    #[test]
+    fn number_keys() {
+        let code = "
+                var m = {0: \"zero\", 1.5: \"one and a half\"};
+                print m[-0];
+                print m[3 / 2];
+                print m[\"0\"] = \"string zero\";
+                print len(m);
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["zero", "one and a half", "string zero", "3", ""]
+        )
+    }
+
+    #[test]
+    fn iterate_keys() {
+        let code = "
+                var ages = {\"cat\": 3, \"ant\": 1, \"bee\": 2};
+                var names = keys(ages);
+                print names;
+                for (var i = 0; i < len(names); i = i + 1) {
+                    print ages[names[i]];
+                }
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["[\"ant\", \"bee\", \"cat\"]", "1", "2", "3", ""]
+        )
+    }
+
+    #[test]
+    fn len() {
+        let code = "
+                print len(\"héllo\");
+                print len([1, 2, 3]);
+                print len({});
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["5", "3", "0", ""])
+    }
+
+    #[test]
+    fn equality() {
+        let code = "
+                print {\"a\": [1]} == {\"a\": [1]};
+                print {\"a\": 1} == {\"a\": 2};
+                print {1: true} == {true: 1};
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["true", "false", "false", ""]
+        )
+    }
+
+    #[test]
+    fn containing_themselves() {
+        let code = "
+                var m = {\"a\": 1};
+                m[\"self\"] = m;
+                print m;
+                var n = {\"a\": 1};
+                n[\"self\"] = n;
+                print m == n;
+                n[\"a\"] = 2;
+                print m == n;
+                var list = [m];
+                m[\"list\"] = list;
+                print list;
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec![
+                "{\"a\": 1, \"self\": {...}}",
+                "true",
+                "false",
+                "[{\"a\": 1, \"list\": [...], \"self\": {...}}]",
+                ""
+            ]
+        )
+    }
+
+    #[test]
+    fn errors() {
+        for (code, message) in [
+            ("print {\"a\": 1}[\"b\"];", "Undefined key \"b\"."),
+            (
+                "var m = {}; m[nil] = 1;",
+                "Map keys must be strings, numbers or booleans, got nil.",
+            ),
+            (
+                "print {[1]: 1};",
+                "Map keys must be strings, numbers or booleans, got [1].",
+            ),
+            (
+                "len(1);",
+                "Can only take the length of strings, lists and maps.",
+            ),
+            ("keys([]);", "Can only get the keys of a map."),
+        ] {
+            assert_eq!(runtime_error(code).message, message);
+        }
+    }
+}
+
+mod interpolation {
+    use crate::parser::test::{execute, get_parser, utf8_to_string};
+
+    #[test]
+    fn parse() {
+        let expr = get_parser("\"sum: ${a + 1}, ${\"inner ${b}\"}!\"")
+            .expression()
+            .unwrap();
+        assert_eq!(
+            expr.pretty_string(),
+            "(interpolate sum:  (+ a 1) ,  (interpolate inner  b) !)"
+        );
+        assert_eq!(
+            expr.to_string_normal(),
+            "\"sum: ${a + 1}, ${\"inner ${b}\"}!\""
+        );
+    }
+
+    #[test]
+    fn stringifies_values() {
+        let code = "
+                var n = 3;
+                var items = [1, \"two\"];
+                fun f() {}
+                class C {}
+                print \"${n} + ${n} = ${n + n}\";
+                print \"${items} ${ {\"k\": nil} } ${true}\";
+                print \"${f} ${C} ${C()}\";
+                print \"${n}${n}\";
+                print \"nested ${\"[${n * 2}]\"} and \\${escaped}\";
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec![
+                "3 + 3 = 6",
+                "[1, \"two\"] {\"k\": nil} true",
+                "<fn f> C C instance",
+                "33",
+                "nested [6] and ${escaped}",
+                ""
+            ]
+        )
+    }
+
+    #[test]
+    fn evaluated_in_scope() {
+        let code = "
+                fun greet(name) {
+                    var greeting = \"hi\";
+                    return \"${greeting}, ${name}\";
+                }
+                print greet(\"bob\");
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["hi, bob", ""])
+    }
+
+    #[test]
+    fn unclosed() {
+        let errors = get_parser("print \"${a b}\";").parse().unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "expected '}' after interpolated expression"
+        );
+    }
+}
+
+mod natives {
+    use crate::{
+        environment::Environment,
+        literal::Literal,
+        native::NativeFunction,
+        parser::test::{execute, get_program, runtime_error, utf8_to_string},
+    };
+    use std::{cell::Cell, rc::Rc};
+
+    #[test]
+    fn conversions() {
+        let code = "
+                print str(1.5) + \"!\";
+                print str([1, \"a\"]);
+                print num(\" 42 \") + 1;
+                print num(-3);
+                print type(1) + \" \" + type(\"\") + \" \" + type(nil) + \" \" + type(true);
+                print type(len) + \" \" + type([]) + \" \" + type({});
+                class A {}
+                fun f() {}
+                print type(A) + \" \" + type(A()) + \" \" + type(f);
+                print len;
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec![
+                "1.5!",
+                "[1, \"a\"]",
+                "43",
+                "-3",
+                "number string nil bool",
+                "function list map",
+                "class instance function",
+                "<native fn len>",
+                ""
+            ]
+        )
+    }
+
+    #[test]
+    fn clock() {
+        let code = "
+                var start = clock();
+                print type(start);
+                print clock() >= start;
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["number", "true", ""])
+    }
+
+    #[test]
+    fn errors() {
+        for (code, message) in [
+            ("num(\"abc\");", "Can't convert \"abc\" to a number."),
+            ("num(nil);", "Can't convert nil to a number."),
+            ("str();", "Expected 1 arguments but got 0."),
+            ("clock(1);", "Expected 0 arguments but got 1."),
+        ] {
+            assert_eq!(runtime_error(code).message, message);
+        }
+    }
+
+    #[test]
+    fn define_native() {
+        let calls = Rc::new(Cell::new(0));
+        let mut environment = Environment::new();
+        environment.define_native(NativeFunction::new("count", 0, {
+            let calls = calls.clone();
+            move |_| {
+                calls.set(calls.get() + 1);
+                Ok(Literal::Number(calls.get() as f64))
+            }
+        }));
+        environment.define_native(NativeFunction::new(
+            "double",
+            1,
+            |arguments| match arguments[0] {
+                Literal::Number(number) => Ok(Literal::Number(number * 2.0)),
+                _ => Err("Can only double numbers.".to_string()),
+            },
+        ));
+
+        let code = "
+                count();
+                print double(count());
+                print double(\"x\");
+            ";
+        let mut buffer = Vec::new();
+        let error = get_program(code)
+            .iter()
+            .find_map(|statement| statement.execute(&mut environment, &mut buffer).err());
+        assert_eq!(utf8_to_string(&buffer), vec!["4", ""]);
+        assert_eq!(error.unwrap().message, "Can only double numbers.");
+        assert_eq!(calls.get(), 2);
+    }
+}
+
+mod strings {
+    use crate::parser::test::{execute, runtime_error, utf8_to_string};
+
+    #[test]
+    fn methods() {
+        let code = r#"
+                var s = "  Hello, wörld  ";
+                print s.len();
+                print s.trim();
+                print s.trim().upper() + s.trim().lower();
+                print s.trim().slice(7, 12);
+                print "abc".slice(0, 0) == "" and "abc".slice(3, 3) == "";
+                print str(s.indexOf("wörld")) + " " + str("abc".indexOf("z"));
+                print s.contains("lo,") and !s.contains("xyz");
+                print "a,b,,c".split(",");
+                print "héy".split("");
+                print ", ".join([1, "a", nil]);
+                print "a-b-c".replace("-", "+");
+                print str("é".charCodeAt(0)) + fromCharCode(65);
+                print fromCharCode("z".charCodeAt(0));
+                var upper = "shout".upper;
+                print upper();
+            "#;
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec![
+                "16",
+                "Hello, wörld",
+                "HELLO, WÖRLDhello, wörld",
+                "wörld",
+                "true",
+                "9 -1",
+                "true",
+                "[\"a\", \"b\", \"\", \"c\"]",
+                "[\"h\", \"é\", \"y\"]",
+                "1, a, nil",
+                "a+b+c",
+                "233A",
+                "z",
+                "SHOUT",
+                ""
+            ]
+        )
+    }
+
+    #[test]
+    fn out_of_range() {
+        for (code, message) in [
+            (
+                "\"abc\".slice(1, 4);",
+                "String index 4 is out of range for a string of length 3.",
+            ),
+            (
+                "\"abc\".charCodeAt(3);",
+                "String index 3 is out of range for a string of length 3.",
+            ),
+            (
+                "\"abc\".slice(-1, 2);",
+                "String index can't be negative, got -1.",
+            ),
+            (
+                "\"abc\".charCodeAt(0.5);",
+                "String index must be a whole number, got 0.5.",
+            ),
+            ("\"abc\".slice(2, 1);", "Slice start 2 is after its end 1."),
+            ("\"\".charCodeAt(nil);", "String index must be a number."),
+            (
+                "fromCharCode(55296);",
+                "55296 isn't a valid character code.",
+            ),
+            ("fromCharCode(-1);", "-1 isn't a valid character code."),
+        ] {
+            assert_eq!(runtime_error(code).message, message, "{code}");
+        }
+    }
+
+    #[test]
+    fn wrong_arguments() {
+        for (code, message) in [
+            ("\"abc\".split(1);", "Argument to split must be a string."),
+            (
+                "\"abc\".replace(\"a\", nil);",
+                "Argument to replace must be a string.",
+            ),
+            ("\",\".join(\"abc\");", "Argument to join must be a list."),
+            ("\"abc\".shout();", "Strings have no method 'shout'."),
+            ("\"abc\".trim(1);", "Expected 0 arguments but got 1."),
+        ] {
+            assert_eq!(runtime_error(code).message, message, "{code}");
+        }
+    }
+}
+
+mod math {
+    use crate::parser::test::{execute, get_parser, runtime_error, utf8_to_string};
+
+    #[test]
+    fn precedence() {
+        for (expr_text, expected) in [
+            ("-2 ** 2", "(- (** 2 2))"),
+            ("2 ** 3 ** 2", "(** 2 (** 3 2))"),
+            ("1 + 7 % 3 * 2", "(+ 1 (* (% 7 3) 2))"),
+            ("7 ~/ 2 ** 2", "(~/ 7 (** 2 2))"),
+        ] {
+            assert_eq!(
+                get_parser(expr_text).expression().unwrap().pretty_string(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn operators() {
+        let code = "
+                print -2 ** 2;
+                print 2 ** 3 ** 2;
+                print 2 ** -1;
+                print 7 % 3;
+                print -7 % 3;
+                print 7.5 % 2;
+                print 7 ~/ 2;
+                print -7 ~/ 2;
+                print 7 / 2;
+                var a = 17; var b = 5;
+                print (a ~/ b) * b + a % b == a;
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["-4", "512", "0.5", "1", "-1", "1.5", "3", "-3", "3.5", "true", ""]
+        )
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let error = runtime_error("print 1 ~/ 0;");
+        assert_eq!(error.message, "Integer division by zero.");
+        assert_eq!(error.code, "E0207");
+        // plain division and remainder follow floating point rules
+        assert_eq!(
+            utf8_to_string(&execute("print 1 / 0; print -1 / 0; print 1 % 0 == 1 % 0;")),
+            vec!["inf", "-inf", "false", ""]
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let code = "
+                print math.sqrt(16);
+                print math.floor(-1.5) + math.ceil(1.2);
+                print math.abs(-3);
+                print math.min(2, 5) + math.max(2, 5);
+                print math.sin(0) + math.cos(0);
+                print math.pow(2, 10);
+                print math;
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["4", "0", "3", "7", "1", "1024", "Math instance", ""]
+        )
+    }
+
+    #[test]
+    fn random() {
+        let code = "
+                math.seed(42);
+                var first = [math.random(), math.random(), math.random()];
+                math.seed(42);
+                var second = [math.random(), math.random(), math.random()];
+                print first == second;
+                print first[0] != first[1];
+                var inRange = true;
+                for (var i = 0; i < 1000; i = i + 1) {
+                    var r = math.random();
+                    if (r < 0 or r >= 1) inRange = false;
+                }
+                print inRange;
+            ";
+        assert_eq!(
+            utf8_to_string(&execute(code)),
+            vec!["true", "true", "true", ""]
+        )
+    }
+
+    #[test]
+    fn wrong_types() {
+        let error = runtime_error("math.sqrt(\"4\");");
+        assert_eq!(error.message, "Arguments to math.sqrt must be numbers.");
+        let error = runtime_error("math.max(1, nil);");
+        assert_eq!(error.message, "Arguments to math.max must be numbers.");
+        let error = runtime_error("print 2 ** \"a\";");
+        assert_eq!(error.code, "E0204");
+    }
+}
+
+mod classes {
+    use crate::parser::test::{execute, runtime_error, utf8_to_string};
+
+    #[test]
+    fn fields_and_printing() {
+        let code = "
+                class Bagel {}
+                var bagel = Bagel();
+                bagel.topping = \"sesame\";
+                print Bagel;
+                print bagel;
+                print bagel.topping;
+            ";
+        let output = vec!["Bagel", "Bagel instance", "sesame", ""];
+        assert_eq!(utf8_to_string(&execute(code)), output)
+    }
+
+    #[test]
+    fn methods_and_this() {
+        let code = "
+                class Counter {
+                    init(start) {
+                        this.count = start;
+                    }
+                    increment() {
+                        this.count = this.count + 1;
+                        return this;
+                    }
+                }
+                var counter = Counter(5);
+                counter.increment().increment();
+                print counter.count;
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["7", ""])
+    }
+
+    #[test]
+    fn bound_methods() {
+        let code = "
+                class Person {
+                    init(name) { this.name = name; }
+                    greet() { print \"hi \" + this.name; }
+                }
+                var greet = Person(\"jane\").greet;
+                greet();
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["hi jane", ""])
+    }
+
+    #[test]
+    fn init_returns_this() {
+        let code = "
+                class Foo {
+                    init() {
+                        this.x = 1;
+                        return;
+                    }
+                }
+                var foo = Foo();
+                print foo.init() == foo;
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["true", ""])
+    }
+
+    #[test]
+    fn fields_shadow_methods() {
+        let code = "
+                class Foo {
+                    bar() { return \"method\"; }
+                }
+                var foo = Foo();
+                print foo.bar();
+                fun field() { return \"field\"; }
+                foo.bar = field;
+                print foo.bar();
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["method", "field", ""])
+    }
+
+    #[test]
+    fn undefined_property() {
+        let code = "
+                class Foo {}
+                Foo().missing;
+            ";
+        assert_eq!(runtime_error(code).message, "Undefined property 'missing'.")
+    }
+
+    #[test]
+    fn init_arity() {
+        let code = "
+                class Foo {
+                    init(a, b) {}
+                }
+                Foo(1);
+            ";
+        assert_eq!(
+            runtime_error(code).message,
+            "Expected 2 arguments but got 1."
+        )
+    }
+
+    #[test]
+    fn properties_on_non_instances() {
+        assert_eq!(
+            runtime_error("var x = 1; x.length;").message,
+            "Only instances have properties."
+        );
+        assert_eq!(
+            runtime_error("var x = 1; x.y = 2;").message,
+            "Only instances have fields."
+        );
+    }
+}
+
+mod inheritance {
+    use crate::{
+        parser::{
+            test::{execute, get_parser, runtime_error, utf8_to_string},
+            ParseError,
+        },
+        span::Span,
+    };
+
+    #[test]
+    fn inherited_methods() {
+        let code = "
+                class Doughnut {
+                    cook() { print \"Fry until golden brown.\"; }
+                }
+                class BostonCream < Doughnut {}
+                BostonCream().cook();
+            ";
+        let output = vec!["Fry until golden brown.", ""];
+        assert_eq!(utf8_to_string(&execute(code)), output)
+    }
+
+    #[test]
+    fn super_calls() {
+        let code = "
+                class A {
+                    method() { print \"A method\"; }
+                }
+                class B < A {
+                    method() { print \"B method\"; }
+                    test() { super.method(); }
+                }
+                class C < B {}
+                C().test();
+            ";
+        assert_eq!(utf8_to_string(&execute(code)), vec!["A method", ""])
+    }
+
+    #[test]
+    fn super_binds_this() {
+        let code = "
+                class Shape {
+                    init(name) { this.name = name; }
+                    describe() { return \"a \" + this.name; }
+                }
+                class Square < Shape {
+                    init() { super.init(\"square\"); }
+                    describe() { return super.describe() + \" with four sides\"; }
+                }
+                print Square().describe();
+            ";
+        let output = vec!["a square with four sides", ""];
+        assert_eq!(utf8_to_string(&execute(code)), output)
+    }
+
+    #[test]
+    fn inherit_from_non_class() {
+        let code = "
+                var NotAClass = \"so not a class\";
+                class Oops < NotAClass {}
+            ";
+        assert_eq!(runtime_error(code).message, "Superclass must be a class.")
+    }
+
+    #[test]
+    fn inherit_from_itself() {
+        let errors = get_parser("class Oops < Oops {}").parse().unwrap_err();
+        assert_eq!(
+            errors,
+            vec![ParseError {
+                message: "a class can't inherit from itself".to_string(),
+                span: Span {
+                    start: 13,
+                    end: 17,
+                    line: 1,
+                    column: 14
+                },
+                code: "E0103",
+                help: None,
+            }]
+        );
+    }
+}
+
+mod spans {
+    use crate::parser::{statement::StmtKind, test::get_program};
+
+    #[test]
+    fn multi_line_statements() {
+        let code = "var total =
+    1 +
+    2;
+if (total > 2) {
+    print total;
+}";
+        let program = get_program(code);
+        let lines: Vec<_> = program
+            .iter()
+            .map(|statement| {
+                (
+                    statement.span.line,
+                    &code[statement.span.start..statement.span.end],
+                )
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (1, "var total =\n    1 +\n    2;"),
+                (4, "if (total > 2) {\n    print total;\n}")
+            ]
+        );
+
+        let StmtKind::If { then_stmt, .. } = &program[1].kind else {
+            panic!("expected an if statement");
+        };
+        let StmtKind::Block(body) = &then_stmt.kind else {
+            panic!("expected a block");
+        };
+        assert_eq!((body[0].span.line, body[0].span.column), (5, 5));
+    }
+}
+
+mod parse_errors {
+    use crate::{
+        parser::{test::get_parser, ParseError},
+        span::Span,
+    };
+
+    #[test]
+    fn reports_every_statement() {
+        let code = "
+                var x = 2
+                print x
+                var = 3;
+                print (1 + );
+                x = x + 1;
+                print x
+                ";
+        let errors = get_parser(code).parse().unwrap_err();
+        let messages: Vec<_> = errors.iter().map(|error| error.message.as_str()).collect();
+        // like jlox, the `print` the first error is found at is skipped along
+        // with the rest of its statement
+        assert_eq!(
+            messages,
+            vec![
+                "expected ';' (SEMICOLON), found 'print' (PRINT)",
+                "expected variable name after 'var'",
+                "expected expression, found RIGHT_PAREN",
+                "expected ';' (SEMICOLON), found '' (EOF)",
+            ]
+        );
+    }
+
+    #[test]
+    fn recovers_inside_blocks() {
+        let code = "
+                fun broken() {
+                    var a = ;
+                }
+                class Fine {}
+                while (true) {
+                    print (;
+                    break;
+                }
+                if (true) print 1
+                ";
+        let errors = get_parser(code).parse().unwrap_err();
+        assert_eq!(
+            errors[0],
+            ParseError {
+                message: "expected expression, found SEMICOLON".to_string(),
+                span: Span {
+                    start: 60,
+                    end: 61,
+                    line: 3,
+                    column: 29
+                },
+                code: "E0100",
+                help: None,
+            }
+        );
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.message.as_str(), error.span.line))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("expected expression, found SEMICOLON", 3),
+                ("expected expression, found SEMICOLON", 7),
+                ("expected ';' (SEMICOLON), found '' (EOF)", 11),
+            ]
+        );
+    }
+
+    #[test]
+    fn keyword_where_an_expression_goes() {
+        let errors = get_parser("print type(print);\nprint 1;")
+            .parse()
+            .unwrap_err();
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.message.as_str(), error.span.line))
+            .collect();
+        assert_eq!(errors, vec![("expected expression, found PRINT", 1)]);
+    }
+
+    #[test]
+    fn invalid_assignment_target() {
+        let errors = get_parser("1 + 2 = 3;").parse().unwrap_err();
+        assert_eq!(errors[0].message, "invalid assigment target");
+        assert_eq!(errors[0].code, "E0101");
+    }
+}
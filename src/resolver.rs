@@ -0,0 +1,419 @@
+use crate::{
+    diagnostic::Diagnostic,
+    parser::{
+        expression::{Expr, ExprKind},
+        statement::{FunctionDecl, Stmt, StmtKind},
+    },
+    span::Span,
+    Context,
+};
+use std::{collections::HashMap, rc::Rc};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    None,
+    Function,
+    /// A method called `init`
+    Initializer,
+    Method,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Walks the program once before it runs, working out how many scopes out
+/// every local variable lives so the interpreter can go straight to it, and
+/// reporting mistakes that don't need the program to run to be found.
+struct Resolver<'a> {
+    /// Local scopes only, globals aren't tracked. A variable maps to whether
+    /// its initializer has finished resolving.
+    scopes: Vec<HashMap<String, bool>>,
+    function: FunctionKind,
+    class: ClassKind,
+    ctx: &'a mut Context,
+}
+
+pub fn resolve(program: &mut [Stmt], ctx: &mut Context) {
+    let mut resolver = Resolver {
+        scopes: vec![],
+        function: FunctionKind::None,
+        class: ClassKind::None,
+        ctx,
+    };
+    resolver.statements(program);
+}
+
+impl Resolver<'_> {
+    fn error(&mut self, code: &'static str, message: &str, span: Span) {
+        self.ctx.error(Diagnostic::new(code, message, span));
+    }
+
+    fn statements(&mut self, statements: &mut [Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &mut Stmt) {
+        match &mut statement.kind {
+            StmtKind::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                self.statements(statements);
+                self.scopes.pop();
+            }
+            StmtKind::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let enclosing_class = self.class;
+                self.class = ClassKind::Class;
+                self.declare(name, statement.span);
+                self.define(name);
+
+                if let Some(superclass) = superclass.as_mut() {
+                    self.class = ClassKind::Subclass;
+                    self.expression(superclass);
+                    self.scopes
+                        .push(HashMap::from([("super".to_string(), true)]));
+                }
+                self.scopes
+                    .push(HashMap::from([("this".to_string(), true)]));
+                for method in methods {
+                    let method = Rc::get_mut(method)
+                        .expect("declarations are only shared once the program is running");
+                    let kind = if method.name == "init" {
+                        FunctionKind::Initializer
+                    } else {
+                        FunctionKind::Method
+                    };
+                    self.function(method, kind, statement.span);
+                }
+                self.scopes.pop();
+                if superclass.is_some() {
+                    self.scopes.pop();
+                }
+                self.class = enclosing_class;
+            }
+            StmtKind::Expression(expr) | StmtKind::Print(expr) => self.expression(expr),
+            StmtKind::Function(declaration) => {
+                let declaration = Rc::get_mut(declaration)
+                    .expect("declarations are only shared once the program is running");
+                // defined straight away so the function can call itself
+                self.declare(&declaration.name, statement.span);
+                self.define(&declaration.name);
+                self.function(declaration, FunctionKind::Function, statement.span);
+            }
+            StmtKind::If {
+                condition,
+                then_stmt,
+                else_stmt,
+            } => {
+                self.expression(condition);
+                self.statement(then_stmt);
+                if let Some(else_stmt) = else_stmt {
+                    self.statement(else_stmt);
+                }
+            }
+            StmtKind::Return(value) => {
+                if self.function == FunctionKind::None {
+                    self.error("E0104", "Can't return from top-level code.", statement.span);
+                }
+                if let Some(value) = value {
+                    if self.function == FunctionKind::Initializer {
+                        let message = "Can't return a value from an initializer.";
+                        self.error("E0111", message, statement.span);
+                    }
+                    self.expression(value);
+                }
+            }
+            StmtKind::Var(name, value) => {
+                self.declare(name, statement.span);
+                self.expression(value);
+                self.define(name);
+            }
+            StmtKind::While {
+                condition,
+                body,
+                increment,
+            } => {
+                self.expression(condition);
+                self.statement(body);
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                }
+            }
+            StmtKind::Break | StmtKind::Continue => {}
+        }
+    }
+
+    fn function(&mut self, declaration: &mut FunctionDecl, kind: FunctionKind, span: Span) {
+        let enclosing_function = self.function;
+        self.function = kind;
+        self.scopes.push(HashMap::new());
+        for param in &declaration.params {
+            self.declare(param, span);
+            self.define(param);
+        }
+        self.statements(&mut declaration.body);
+        self.scopes.pop();
+        self.function = enclosing_function;
+    }
+
+    fn expression(&mut self, expr: &mut Expr) {
+        match &mut expr.kind {
+            ExprKind::Grouping(expr) => self.expression(expr),
+            ExprKind::Literal(_) => {}
+            ExprKind::Unary { expr, .. } => self.expression(expr),
+            ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExprKind::This { depth } => {
+                if self.class == ClassKind::None {
+                    self.error("E0105", "Can't use 'this' outside of a class.", expr.span);
+                }
+                *depth = self.depth_of("this");
+            }
+            ExprKind::Variable { name, depth } => {
+                if self.scopes.last().and_then(|scope| scope.get(name)) == Some(&false) {
+                    self.error(
+                        "E0106",
+                        "Can't read local variable in its own initializer.",
+                        expr.span,
+                    );
+                }
+                *depth = self.depth_of(name);
+            }
+            ExprKind::Assign { name, value, depth } => {
+                self.expression(value);
+                *depth = self.depth_of(name);
+            }
+            ExprKind::Call { callee, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            ExprKind::Get { object, .. } => self.expression(object),
+            ExprKind::Interpolation(elements) | ExprKind::List(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+            }
+            ExprKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            }
+            ExprKind::Set { object, value, .. } => {
+                self.expression(value);
+                self.expression(object);
+            }
+            ExprKind::Super { depth, .. } => {
+                match self.class {
+                    ClassKind::None => {
+                        self.error("E0108", "Can't use 'super' outside of a class.", expr.span)
+                    }
+                    ClassKind::Class => self.error(
+                        "E0109",
+                        "Can't use 'super' in a class with no superclass.",
+                        expr.span,
+                    ),
+                    ClassKind::Subclass => {}
+                }
+                *depth = self.depth_of("super");
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &str, span: Span) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        if scope.insert(name.to_string(), false).is_some() {
+            self.error(
+                "E0107",
+                "Already a variable with this name in this scope.",
+                span,
+            );
+        }
+    }
+
+    fn define(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), true);
+        }
+    }
+
+    /// `None` if `name` isn't a local, in which case it must be a global
+    fn depth_of(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::resolve;
+    use crate::{
+        parser::{
+            expression::ExprKind,
+            statement::{Stmt, StmtKind},
+            Parser,
+        },
+        scanner::tokenize,
+        Context,
+    };
+
+    fn resolved(src: &str) -> (Vec<Stmt>, Context) {
+        let mut context = Context::new();
+        let tokens = tokenize(src, &mut context);
+        let mut program = Parser::new(tokens).parse().unwrap();
+        resolve(&mut program, &mut context);
+        (program, context)
+    }
+
+    /// The code and message of every error found in `src`
+    fn errors(src: &str) -> Vec<(&'static str, String)> {
+        resolved(src)
+            .1
+            .errors
+            .into_iter()
+            .map(|error| (error.code, error.message))
+            .collect()
+    }
+
+    #[test]
+    fn depths() {
+        let (program, context) = resolved(
+            "var global = 1;
+            {
+                var outer = 2;
+                {
+                    print outer + global;
+                }
+            }",
+        );
+        assert!(context.errors.is_empty());
+
+        let StmtKind::Block(outer) = &program[1].kind else {
+            panic!("expected a block");
+        };
+        let StmtKind::Block(inner) = &outer[1].kind else {
+            panic!("expected a block");
+        };
+        let StmtKind::Print(sum) = &inner[0].kind else {
+            panic!("expected a print statement");
+        };
+        let ExprKind::Binary { left, right, .. } = &sum.kind else {
+            panic!("expected a binary expression");
+        };
+        assert!(matches!(
+            left.kind,
+            ExprKind::Variable { depth: Some(1), .. }
+        ));
+        assert!(matches!(right.kind, ExprKind::Variable { depth: None, .. }));
+    }
+
+    #[test]
+    fn top_level_return() {
+        assert_eq!(
+            errors("return 1;"),
+            vec![("E0104", "Can't return from top-level code.".to_string())]
+        );
+        assert!(errors("fun f() { return 1; }").is_empty());
+    }
+
+    #[test]
+    fn return_from_initializer() {
+        assert_eq!(
+            errors("class A { init() { return 1; } } class B { init() { return nil; } }"),
+            vec![
+                (
+                    "E0111",
+                    "Can't return a value from an initializer.".to_string()
+                );
+                2
+            ]
+        );
+        assert!(errors(
+            "class A {
+                init() { if (true) return; fun f() { return 1; } }
+                method() { return 1; }
+            }"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn this_outside_class() {
+        assert_eq!(
+            errors("print this; fun f() { return this; }"),
+            vec![("E0105", "Can't use 'this' outside of a class.".to_string()); 2]
+        );
+        assert!(errors("class A { get() { return this; } }").is_empty());
+    }
+
+    #[test]
+    fn own_initializer() {
+        assert_eq!(
+            errors("var a = 1; { var a = a; }"),
+            vec![(
+                "E0106",
+                "Can't read local variable in its own initializer.".to_string()
+            )]
+        );
+        // globals can be redefined in terms of themselves
+        assert!(errors("var a = 1; var a = a + 1;").is_empty());
+    }
+
+    #[test]
+    fn redeclared_local() {
+        assert_eq!(
+            errors("{ var a = 1; var a = 2; } fun f(x, x) {}"),
+            vec![
+                (
+                    "E0107",
+                    "Already a variable with this name in this scope.".to_string()
+                );
+                2
+            ]
+        );
+        assert!(errors("var a = 1; var a = 2; { var a = 3; }").is_empty());
+    }
+
+    #[test]
+    fn misplaced_super() {
+        assert_eq!(
+            errors("super.method(); class A { method() { super.method(); } }"),
+            vec![
+                ("E0108", "Can't use 'super' outside of a class.".to_string()),
+                (
+                    "E0109",
+                    "Can't use 'super' in a class with no superclass.".to_string()
+                )
+            ]
+        );
+    }
+}
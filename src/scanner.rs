@@ -1,5 +1,7 @@
-use crate::Context;
+use crate::{diagnostic::Diagnostic, span::Span, Context};
+use std::{iter::Peekable, str::CharIndices};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Debug)]
 pub enum Token {
     LeftParen,
@@ -9,8 +11,12 @@ pub enum Token {
     RightBracket,
     LeftBracket,
     Star,
+    StarStar,
+    Percent,
+    TildeSlash,
     Dot,
     Comma,
+    Colon,
     Plus,
     Minus,
     SemiColon,
@@ -25,13 +31,19 @@ pub enum Token {
     EOF,
     Slash,
     String(String),
+    /// The text of a string literal up to an embedded `${`, the tokens of the
+    /// expression follow and the rest of the string comes after them as
+    /// another `Interpolation` or a `String`.
+    Interpolation(String),
     /// String is the string from which the number was generated. eg: 42 and 42.0 have same vbalue
     /// but not the same string, this is just for testing and reporting, idk a better way to do
     /// this.
     Number(String, f64),
     Identifier(String),
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     For,
@@ -58,8 +70,12 @@ impl Token {
             Token::RightBracket => "RIGHT_BRACKET",
             Token::LeftBracket => "LEFT_BRACKET",
             Token::Star => "STAR",
+            Token::StarStar => "STAR_STAR",
+            Token::Percent => "PERCENT",
+            Token::TildeSlash => "TILDE_SLASH",
             Token::Dot => "DOT",
             Token::Comma => "COMMA",
+            Token::Colon => "COLON",
             Token::Plus => "PLUS",
             Token::Minus => "MINUS",
             Token::SemiColon => "SEMICOLON",
@@ -73,10 +89,13 @@ impl Token {
             Token::GreaterEqual => "GREATER_EQUAL",
             Token::Slash => "SLASH",
             Token::String(_) => "STRING",
+            Token::Interpolation(_) => "INTERPOLATION",
             Token::Number(_, _) => "NUMBER",
             Token::Identifier(_) => "IDENTIFIER",
             Token::And => "AND",
+            Token::Break => "BREAK",
             Token::Class => "CLASS",
+            Token::Continue => "CONTINUE",
             Token::Else => "ELSE",
             Token::False => "FALSE",
             Token::For => "FOR",
@@ -100,11 +119,15 @@ impl Token {
             Token::RightParen => ")",
             Token::LeftBrace => "{",
             Token::RightBrace => "}",
-            Token::RightBracket => "[",
-            Token::LeftBracket => "]",
+            Token::RightBracket => "]",
+            Token::LeftBracket => "[",
             Token::Star => "*",
+            Token::StarStar => "**",
+            Token::Percent => "%",
+            Token::TildeSlash => "~/",
             Token::Dot => ".",
             Token::Comma => ",",
+            Token::Colon => ":",
             Token::Plus => "+",
             Token::Minus => "-",
             Token::SemiColon => ";",
@@ -118,10 +141,13 @@ impl Token {
             Token::GreaterEqual => ">=",
             Token::Slash => "/",
             Token::String(text) => return format!("\"{text}\""),
-            Token::Number(num_as_str, _) => return format!("{num_as_str}"),
-            Token::Identifier(ident) => return format!("{ident}"),
+            Token::Interpolation(text) => return format!("\"{text}${{"),
+            Token::Number(num_as_str, _) => return num_as_str.to_string(),
+            Token::Identifier(ident) => return ident.to_string(),
             Token::And => "and",
+            Token::Break => "break",
             Token::Class => "class",
+            Token::Continue => "continue",
             Token::Else => "else",
             Token::False => "false",
             Token::For => "for",
@@ -141,7 +167,7 @@ impl Token {
 
     pub fn literal(&self) -> String {
         match self {
-            Token::String(text) => text,
+            Token::String(text) | Token::Interpolation(text) => text,
             Token::Number(_, num) => {
                 if num.fract() == 0.0 {
                     return format!("{}.0", num);
@@ -154,31 +180,90 @@ impl Token {
     }
 }
 
+/// A token along with where in the source it was found
+#[derive(PartialEq, Debug)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+impl PartialEq<Token> for SpannedToken {
+    fn eq(&self, other: &Token) -> bool {
+        &self.token == other
+    }
+}
+
 fn is_valid_identifier_char(c: char) -> bool {
     c.is_ascii_alphabetic() || c == '_'
 }
-pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<Token> {
+
+/// The `{1F600}` part of a `\u{1F600}` escape, `None` if it isn't 1 to 6 hex
+/// digits between braces naming a valid character. Stops before anything that
+/// can't be part of the escape, so a closing `"` is never swallowed.
+fn unicode_escape(chars: &mut Peekable<CharIndices>) -> Option<char> {
+    chars.next_if(|&(_, character)| character == '{')?;
+    let mut digits = std::string::String::new();
+    while let Some((_, digit)) = chars.next_if(|(_, character)| character.is_ascii_hexdigit()) {
+        digits.push(digit);
+    }
+    chars.next_if(|&(_, character)| character == '}')?;
+    if digits.is_empty() || digits.len() > 6 {
+        return None;
+    }
+    char::from_u32(u32::from_str_radix(&digits, 16).ok()?)
+}
+
+pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<SpannedToken> {
     use Token::*;
     let mut tokens = vec![];
     let mut line: u64 = 1;
+    // byte offset of the first character on the current line
+    let mut line_start = 0;
+    let column_of = |line_start: usize, offset: usize| -> u64 {
+        input[line_start..offset].chars().count() as u64 + 1
+    };
+
+    // one entry for each `${` we are inside of, counting the `{`s opened
+    // since so we know which `}` goes back to the string
+    let mut interpolations: Vec<usize> = vec![];
 
-    let mut chars = input.chars().peekable();
-    'main: while let Some(char) = chars.next() {
-        tokens.push(match char {
+    let mut chars = input.char_indices().peekable();
+    'main: while let Some((start, char)) = chars.next() {
+        let (token_line, column) = (line, column_of(line_start, start));
+        let token = match char {
             '(' => LeftParen,
             ')' => RightParen,
-            '{' => LeftBrace,
-            '}' => RightBrace,
+            '{' => {
+                if let Some(open_braces) = interpolations.last_mut() {
+                    *open_braces += 1;
+                }
+                LeftBrace
+            }
+            '}' if interpolations.last() != Some(&0) => {
+                if let Some(open_braces) = interpolations.last_mut() {
+                    *open_braces -= 1;
+                }
+                RightBrace
+            }
             '[' => LeftBracket,
             ']' => RightBracket,
-            '*' => Star,
+            '*' => {
+                if chars.next_if(|&(_, next)| next == '*').is_some() {
+                    StarStar
+                } else {
+                    Star
+                }
+            }
+            '%' => Percent,
+            '~' if chars.next_if(|&(_, next)| next == '/').is_some() => TildeSlash,
             '.' => Dot,
             '+' => Plus,
             '-' => Minus,
             ';' => SemiColon,
             ',' => Comma,
+            ':' => Colon,
             '=' => {
-                if let Some('=') = chars.peek() {
+                if let Some((_, '=')) = chars.peek() {
                     chars.next();
                     EqualEqual
                 } else {
@@ -186,7 +271,7 @@ pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<Token> {
                 }
             }
             '!' => {
-                if let Some('=') = chars.peek() {
+                if let Some((_, '=')) = chars.peek() {
                     chars.next();
                     BangEqual
                 } else {
@@ -194,7 +279,7 @@ pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<Token> {
                 }
             }
             '<' => {
-                if let Some('=') = chars.peek() {
+                if let Some((_, '=')) = chars.peek() {
                     chars.next();
                     LessEqual
                 } else {
@@ -202,7 +287,7 @@ pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<Token> {
                 }
             }
             '>' => {
-                if let Some('=') = chars.peek() {
+                if let Some((_, '=')) = chars.peek() {
                     chars.next();
                     GreaterEqual
                 } else {
@@ -210,11 +295,43 @@ pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<Token> {
                 }
             }
             '/' => {
-                if let Some('/') = chars.peek() {
-                    while let Some(character) = chars.next() {
+                if let Some((_, '/')) = chars.peek() {
+                    for (index, character) in chars.by_ref() {
                         if character == '\n' {
                             line += 1;
+                            line_start = index + 1;
+                            break;
+                        }
+                    }
+                    continue;
+                } else if chars.next_if(|&(_, next)| next == '*').is_some() {
+                    // block comments nest, so count how many are still open
+                    let mut depth = 1;
+                    while depth > 0 {
+                        let Some((index, character)) = chars.next() else {
+                            ctx.error(
+                                Diagnostic::new(
+                                    "E0005",
+                                    "Unterminated block comment.",
+                                    Span {
+                                        start,
+                                        end: start + 2,
+                                        line: token_line,
+                                        column,
+                                    },
+                                )
+                                .with_help("close it with '*/'"),
+                            );
                             break;
+                        };
+                        match character {
+                            '/' if chars.next_if(|&(_, next)| next == '*').is_some() => depth += 1,
+                            '*' if chars.next_if(|&(_, next)| next == '/').is_some() => depth -= 1,
+                            '\n' => {
+                                line += 1;
+                                line_start = index + 1;
+                            }
+                            _ => {}
                         }
                     }
                     continue;
@@ -223,34 +340,102 @@ pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<Token> {
                 }
             }
 
-            '"' => {
+            // a `}` that gets here ends an interpolation, the string carries on
+            '"' | '}' => {
+                if char == '}' {
+                    interpolations.pop();
+                }
                 let mut text = "".to_string();
                 loop {
-                    if let Some(character) = chars.next() {
-                        if character == '"' {
-                            break;
-                        } else {
+                    let Some((index, character)) = chars.next() else {
+                        ctx.error(
+                            Diagnostic::new(
+                                "E0002",
+                                "Unterminated string.",
+                                Span {
+                                    start,
+                                    end: input.len(),
+                                    line: token_line,
+                                    column,
+                                },
+                            )
+                            .with_note("the string runs on to the end of the file")
+                            .with_help("add a closing '\"'"),
+                        );
+                        continue 'main;
+                    };
+                    match character {
+                        '"' => break String(text),
+                        '$' if chars.next_if(|&(_, next)| next == '{').is_some() => {
+                            interpolations.push(0);
+                            break Interpolation(text);
+                        }
+                        '\n' => {
                             text.push(character);
+                            line += 1;
+                            line_start = index + 1;
                         }
-                    } else {
-                        ctx.error("Unterminated string.", line);
-                        continue 'main;
+                        '\\' => {
+                            let escaped = match chars.next() {
+                                Some((_, 'n')) => Ok('\n'),
+                                Some((_, 't')) => Ok('\t'),
+                                Some((_, 'r')) => Ok('\r'),
+                                Some((_, '0')) => Ok('\0'),
+                                Some((_, '"')) => Ok('"'),
+                                Some((_, '\\')) => Ok('\\'),
+                                Some((_, '$')) => Ok('$'),
+                                Some((_, 'u')) => unicode_escape(&mut chars).ok_or_else(|| {
+                                    Diagnostic::new(
+                                        "E0004",
+                                        "Invalid unicode escape.",
+                                        Span::default(),
+                                    )
+                                    .with_help(
+                                        "write the code point in hex between braces, eg. '\\u{1F600}'",
+                                    )
+                                }),
+                                Some((_, unknown)) => Err(Diagnostic::new(
+                                    "E0003",
+                                    format!("Unknown escape sequence '\\{unknown}'."),
+                                    Span::default(),
+                                )
+                                .with_help(
+                                    "use '\\\\' for a backslash, the valid escapes are \\n \\t \\r \\0 \\\" \\\\ \\$ and \\u{...}",
+                                )),
+                                // reported as an unterminated string next time round
+                                None => continue,
+                            };
+                            match escaped {
+                                Ok(escaped) => text.push(escaped),
+                                Err(diagnostic) => ctx.error(Diagnostic {
+                                    // from the backslash to the end of the escape
+                                    span: Span {
+                                        start: index,
+                                        end: chars.peek().map_or(input.len(), |&(end, _)| end),
+                                        line,
+                                        column: column_of(line_start, index),
+                                    },
+                                    ..diagnostic
+                                }),
+                            }
+                        }
+                        character => text.push(character),
                     }
                 }
-                String(text)
             }
             '\n' => {
                 line += 1;
+                line_start = start + 1;
                 continue;
             }
             '\t' | ' ' | '\r' => {
                 continue;
             }
-            num if num.is_digit(10) => {
+            num if num.is_ascii_digit() => {
                 let mut encountered_decimal_point = false;
                 let mut as_string = num.to_string();
-                while let Some(next) = chars.peek() {
-                    if !next.is_digit(10) {
+                while let Some((_, next)) = chars.peek() {
+                    if !next.is_ascii_digit() {
                         if *next != '.' {
                             break;
                         }
@@ -264,7 +449,8 @@ pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<Token> {
                     as_string.push(
                         chars
                             .next()
-                            .expect("peeked, this branch should only be run if peek wasn't None"),
+                            .expect("peeked, this branch should only be run if peek wasn't None")
+                            .1,
                     );
                 }
 
@@ -276,7 +462,7 @@ pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<Token> {
 
             letter if is_valid_identifier_char(letter) => {
                 let mut name = letter.to_string();
-                while let Some(next) = chars.peek() {
+                while let Some((_, next)) = chars.peek() {
                     if !(next.is_ascii_alphanumeric() || *next == '_') {
                         break;
                     }
@@ -284,13 +470,16 @@ pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<Token> {
                     name.push(
                         chars
                             .next()
-                            .expect("peeked, this branch should only be run if peek wasn't None"),
+                            .expect("peeked, this branch should only be run if peek wasn't None")
+                            .1,
                     );
                 }
 
                 match name.as_ref() {
                     "and" => And,
+                    "break" => Break,
                     "class" => Class,
+                    "continue" => Continue,
                     "else" => Else,
                     "false" => False,
                     "for" => For,
@@ -310,12 +499,39 @@ pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<Token> {
             }
 
             invalid => {
-                ctx.error(&format!("Unexpected character: '{invalid}'"), line);
+                ctx.error(Diagnostic::new(
+                    "E0001",
+                    format!("Unexpected character: '{invalid}'"),
+                    Span {
+                        start,
+                        end: start + invalid.len_utf8(),
+                        line: token_line,
+                        column,
+                    },
+                ));
                 continue;
             } // Ignore other characters for now
+        };
+        let end = chars.peek().map_or(input.len(), |&(index, _)| index);
+        tokens.push(SpannedToken {
+            token,
+            span: Span {
+                start,
+                end,
+                line: token_line,
+                column,
+            },
         });
     }
-    tokens.push(EOF);
+    tokens.push(SpannedToken {
+        token: EOF,
+        span: Span {
+            start: input.len(),
+            end: input.len(),
+            line,
+            column: column_of(line_start, input.len()),
+        },
+    });
     tokens
 }
 
@@ -323,11 +539,17 @@ pub fn tokenize(input: &str, ctx: &mut Context) -> Vec<Token> {
 mod test {
     use super::*;
 
-    // Mock Context struct for testing purposes
+    /// The message and line of every error reported while scanning
+    fn errors(ctx: &Context) -> Vec<(String, u64)> {
+        ctx.errors
+            .iter()
+            .map(|error| (error.message.clone(), error.span.line))
+            .collect()
+    }
 
     #[test]
     fn test_single_char_tokens() {
-        let input = "+ - * / ( ) { } ; , .";
+        let input = "+ - * / ( ) { } ; , . :";
         let mut ctx = Context::new();
         let tokens = tokenize(input, &mut ctx);
 
@@ -343,6 +565,7 @@ mod test {
             Token::SemiColon,
             Token::Comma,
             Token::Dot,
+            Token::Colon,
             Token::EOF,
         ];
 
@@ -351,6 +574,24 @@ mod test {
     }
 
     #[test]
+    fn test_bracket_lexemes() {
+        let mut ctx = Context::new();
+        let lexemes: Vec<_> = tokenize("[ ]", &mut ctx)
+            .into_iter()
+            .map(|token| (token.token.token_type(), token.token.lexeme()))
+            .collect();
+        assert_eq!(
+            lexemes,
+            vec![
+                ("LEFT_BRACKET".to_string(), "[".to_string()),
+                ("RIGHT_BRACKET".to_string(), "]".to_string()),
+                ("EOF".to_string(), "".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
     fn test_numbers() {
         let input = "42 3.14 0.5";
         let mut ctx = Context::new();
@@ -382,7 +623,121 @@ mod test {
         assert_eq!(tokens, expected_tokens);
         // There should be one error for the unterminated string
         assert_eq!(ctx.errors.len(), 1);
-        assert_eq!(ctx.errors[0], ("Unterminated string.".to_string(), 1));
+        assert_eq!(errors(&ctx)[0], ("Unterminated string.".to_string(), 1));
+        assert_eq!(ctx.errors[0].code, "E0002");
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let input = r#""a\nb\tc\r\0 \"quoted\" back\\slash" "\u{41}\u{e9}\u{1F600}""#;
+        let mut ctx = Context::new();
+        let tokens = tokenize(input, &mut ctx);
+
+        let expected_tokens = vec![
+            Token::String("a\nb\tc\r\0 \"quoted\" back\\slash".to_string()),
+            Token::String("Aé😀".to_string()),
+            Token::EOF,
+        ];
+
+        assert_eq!(tokens, expected_tokens);
+        assert!(ctx.errors.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_escapes() {
+        let input = "\"ok\nstill \\q ok\"\n\"\\u{110000} \\u{} \\u41 \\u{1234567}\" done";
+        let mut ctx = Context::new();
+        let tokens = tokenize(input, &mut ctx);
+
+        // bad escapes are left out but the rest of the string is kept
+        let expected_tokens = vec![
+            Token::String("ok\nstill  ok".to_string()),
+            Token::String("  41 ".to_string()),
+            Token::Identifier("done".to_string()),
+            Token::EOF,
+        ];
+        assert_eq!(tokens, expected_tokens);
+
+        let errors: Vec<_> = ctx
+            .errors
+            .iter()
+            .map(|error| {
+                (
+                    error.code,
+                    &input[error.span.start..error.span.end],
+                    error.span.line,
+                    error.span.column,
+                )
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("E0003", "\\q", 2, 7),
+                ("E0004", "\\u{110000}", 3, 2),
+                ("E0004", "\\u{}", 3, 13),
+                ("E0004", "\\u", 3, 18),
+                ("E0004", "\\u{1234567}", 3, 23),
+            ]
+        );
+        assert_eq!(ctx.errors[0].message, "Unknown escape sequence '\\q'.");
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let input = r#""a ${x} b ${ {1: "${y}"}[1] }" "\${z}""#;
+        let mut ctx = Context::new();
+        let tokens = tokenize(input, &mut ctx);
+
+        let expected_tokens = vec![
+            Token::Interpolation("a ".to_string()),
+            Token::Identifier("x".to_string()),
+            Token::Interpolation(" b ".to_string()),
+            Token::LeftBrace,
+            Token::Number("1".to_string(), 1.0),
+            Token::Colon,
+            Token::Interpolation("".to_string()),
+            Token::Identifier("y".to_string()),
+            Token::String("".to_string()),
+            Token::RightBrace,
+            Token::LeftBracket,
+            Token::Number("1".to_string(), 1.0),
+            Token::RightBracket,
+            Token::String("".to_string()),
+            Token::String("${z}".to_string()),
+            Token::EOF,
+        ];
+
+        assert_eq!(tokens, expected_tokens);
+        assert!(ctx.errors.is_empty());
+    }
+
+    #[test]
+    fn test_multiline_string_lines() {
+        let input = "var s = \"one\ntwo\nthree\";\nprint s;\n\"unterminated\n";
+        let mut ctx = Context::new();
+        let tokens = tokenize(input, &mut ctx);
+
+        let lines: Vec<_> = tokens
+            .iter()
+            .map(|token| (token.token.lexeme(), token.span.line))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("var".to_string(), 1),
+                ("s".to_string(), 1),
+                ("=".to_string(), 1),
+                ("\"one\ntwo\nthree\"".to_string(), 1),
+                (";".to_string(), 3),
+                ("print".to_string(), 4),
+                ("s".to_string(), 4),
+                (";".to_string(), 4),
+                ("".to_string(), 6),
+            ]
+        );
+        // points at where the string started
+        assert_eq!(errors(&ctx), vec![("Unterminated string.".to_string(), 5)]);
     }
 
     #[test]
@@ -437,6 +792,26 @@ mod test {
         assert!(ctx.errors.is_empty());
     }
 
+    #[test]
+    fn test_arithmetic_operators() {
+        let input = "* ** % ~/ / ~";
+        let mut ctx = Context::new();
+        let tokens = tokenize(input, &mut ctx);
+
+        let expected_tokens = vec![
+            Token::Star,
+            Token::StarStar,
+            Token::Percent,
+            Token::TildeSlash,
+            Token::Slash,
+            Token::EOF,
+        ];
+
+        assert_eq!(tokens, expected_tokens);
+        // a lone `~` isn't an operator
+        assert_eq!(ctx.errors.len(), 1);
+    }
+
     #[test]
     fn test_comments() {
         let input = "// This is a comment\nvar x = 42; // Another comment\nx;";
@@ -460,7 +835,7 @@ mod test {
 
     #[test]
     fn test_invalid_characters() {
-        let input = "@ # $ % ^ &";
+        let input = "@ # $ ~ ^ &";
         let mut ctx = Context::new();
         let tokens = tokenize(input, &mut ctx);
 
@@ -474,11 +849,11 @@ mod test {
             ("Unexpected character: '@'".to_string(), 1),
             ("Unexpected character: '#'".to_string(), 1),
             ("Unexpected character: '$'".to_string(), 1),
-            ("Unexpected character: '%'".to_string(), 1),
+            ("Unexpected character: '~'".to_string(), 1),
             ("Unexpected character: '^'".to_string(), 1),
             ("Unexpected character: '&'".to_string(), 1),
         ];
-        assert_eq!(ctx.errors, expected_errors);
+        assert_eq!(errors(&ctx), expected_errors);
     }
 
     #[test]
@@ -514,6 +889,31 @@ mod test {
         assert!(ctx.errors.is_empty());
     }
 
+    #[test]
+    fn test_loop_control_keywords() {
+        let input = "while (true) { break; continue; } breaker";
+        let mut ctx = Context::new();
+        let tokens = tokenize(input, &mut ctx);
+
+        let expected_tokens = vec![
+            Token::While,
+            Token::LeftParen,
+            Token::True,
+            Token::RightParen,
+            Token::LeftBrace,
+            Token::Break,
+            Token::SemiColon,
+            Token::Continue,
+            Token::SemiColon,
+            Token::RightBrace,
+            Token::Identifier("breaker".to_string()),
+            Token::EOF,
+        ];
+
+        assert_eq!(tokens, expected_tokens);
+        assert!(ctx.errors.is_empty());
+    }
+
     #[test]
     fn test_identifiers_with_underscores_and_digits() {
         let input = "_var var1 var_name";
@@ -545,6 +945,35 @@ mod test {
         assert_eq!(tokens, expected_tokens);
     }
 
+    #[test]
+    fn test_block_comments() {
+        let input =
+            "1 /* one */ 2 /* two\nlines */ 3\n/* outer /* inner\n */ still a comment */ 4 */* 5";
+        let mut ctx = Context::new();
+        let tokens = tokenize(input, &mut ctx);
+
+        let lines: Vec<_> = tokens
+            .iter()
+            .map(|token| (token.token.lexeme(), token.span.line))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("1".to_string(), 1),
+                ("2".to_string(), 1),
+                ("3".to_string(), 2),
+                ("4".to_string(), 4),
+                ("*".to_string(), 4),
+                ("".to_string(), 4),
+            ]
+        );
+        assert_eq!(
+            errors(&ctx),
+            vec![("Unterminated block comment.".to_string(), 4)]
+        );
+        assert_eq!(ctx.errors[0].code, "E0005");
+    }
+
     #[test]
     fn test_multiline_comments() {
         let input = "// Comment line 1\n// Comment line 2\nvar x = 10;";
@@ -563,4 +992,37 @@ mod test {
         assert_eq!(tokens, expected_tokens);
         assert!(ctx.errors.is_empty());
     }
+
+    #[test]
+    fn test_spans() {
+        let input = "var x\n  = \"hi\"; // done\nx;";
+        let mut ctx = Context::new();
+        let tokens = tokenize(input, &mut ctx);
+
+        let spans: Vec<_> = tokens
+            .iter()
+            .map(|token| {
+                let Span {
+                    start,
+                    end,
+                    line,
+                    column,
+                } = token.span;
+                (&input[start..end], line, column)
+            })
+            .collect();
+        let expected_spans = vec![
+            ("var", 1, 1),
+            ("x", 1, 5),
+            ("=", 2, 3),
+            ("\"hi\"", 2, 5),
+            (";", 2, 9),
+            ("x", 3, 1),
+            (";", 3, 2),
+            ("", 3, 3),
+        ];
+
+        assert_eq!(spans, expected_spans);
+        assert!(ctx.errors.is_empty());
+    }
 }
@@ -0,0 +1,19 @@
+/// A region of the source code. `start` and `end` are byte offsets into the
+/// source, `line` and `column` are 1-based and describe where `start` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u64,
+    pub column: u64,
+}
+
+impl Span {
+    /// Span from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+}
@@ -0,0 +1,520 @@
+//! A second backend in the style of clox: programs are compiled to bytecode
+//! and run on a stack machine instead of walking the AST.
+
+pub mod bytecode;
+pub mod chunk;
+pub mod compiler;
+pub mod disassembler;
+pub mod object;
+#[cfg(test)]
+mod test;
+
+use crate::{
+    environment::Environment,
+    error::RuntimeError,
+    gc::{self, Rooted, Tracer},
+    literal::Literal,
+    parser::expression::{
+        binary, get_index, map_key, print, property, set_index, unary, undefined_variable,
+        wrong_arity,
+    },
+    scanner::Token,
+    span::Span,
+};
+use chunk::{CompiledFunction, Constant, OpCode};
+use object::{BoundMethod, Class, Closure, Instance, Upvalue};
+use std::{cell::RefCell, collections::BTreeMap, io::Write, rc::Rc};
+
+/// How deep calls can nest before the VM reports a stack overflow
+const FRAMES_MAX: usize = 4096;
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    /// The next instruction to run once this frame is back on top
+    ip: usize,
+    /// Stack slot of the callee, its arguments and locals follow it
+    base: usize,
+}
+
+struct Vm<'a, W> {
+    stack: Vec<Literal>,
+    frames: Vec<CallFrame>,
+    /// Upvalues still pointing at the stack, closed when their slot is popped
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    globals: &'a mut Environment,
+    output: &'a mut W,
+}
+
+/// Runs a compiled script, returning the value it returns. Globals are read
+/// from and defined in `globals`, so they are shared with the tree-walker.
+pub fn run<W: Write>(
+    script: Rc<CompiledFunction>,
+    globals: &mut Environment,
+    output: &mut W,
+) -> Result<Literal, RuntimeError> {
+    let closure = gc::manage(Closure {
+        function: script,
+        upvalues: vec![],
+    });
+    let mut vm = Vm {
+        stack: vec![Literal::Closure(closure.clone())],
+        frames: vec![CallFrame {
+            closure,
+            ip: 0,
+            base: 0,
+        }],
+        open_upvalues: vec![],
+        globals,
+        output,
+    };
+    vm.run()
+}
+
+impl<W: Write> Vm<'_, W> {
+    fn push(&mut self, value: Literal) {
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Literal {
+        self.stack
+            .pop()
+            .expect("the compiler keeps the stack balanced")
+    }
+
+    fn peek(&self, distance: usize) -> &Literal {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// Reports everything the program can still reach to the collector
+    fn trace(&self, tracer: &mut Tracer) {
+        self.stack.iter().for_each(|value| tracer.literal(value));
+        self.frames
+            .iter()
+            .for_each(|frame| tracer.object(&frame.closure));
+        self.open_upvalues
+            .iter()
+            .for_each(|upvalue| tracer.object(upvalue));
+        self.globals.trace(tracer);
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("a frame is running")
+    }
+
+    fn run(&mut self) -> Result<Literal, RuntimeError> {
+        let mut closure = self.frame().closure.clone();
+        let mut ip = 0;
+        let mut base = 0;
+
+        loop {
+            gc::safe_point(|roots| self.trace(roots));
+            let chunk = &closure.function.chunk;
+            let span = chunk.spans[ip];
+            let op = OpCode::from_byte(chunk.code[ip]).expect("the compiler only emits opcodes");
+            ip += 1;
+
+            macro_rules! read_byte {
+                () => {{
+                    ip += 1;
+                    chunk.code[ip - 1]
+                }};
+            }
+            macro_rules! read_u16 {
+                () => {{
+                    ip += 2;
+                    chunk.read_u16(ip - 2)
+                }};
+            }
+            macro_rules! read_string {
+                () => {
+                    match &chunk.constants[read_u16!() as usize] {
+                        Constant::String(text) => &**text,
+                        constant => unreachable!("names are string constants, got {constant:?}"),
+                    }
+                };
+            }
+
+            match op {
+                OpCode::Constant => {
+                    let value = match &chunk.constants[read_u16!() as usize] {
+                        Constant::Number(number) => Literal::Number(*number),
+                        Constant::String(text) => Literal::String(text.clone()),
+                        Constant::Function(_) => {
+                            unreachable!("functions are only loaded by OpCode::Closure")
+                        }
+                    };
+                    self.push(value);
+                }
+                OpCode::Nil => self.push(Literal::Nil),
+                OpCode::True => self.push(Literal::True),
+                OpCode::False => self.push(Literal::False),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = read_byte!() as usize;
+                    self.push(self.stack[base + slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = read_byte!() as usize;
+                    self.stack[base + slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = read_string!();
+                    let value = self
+                        .globals
+                        .get(name, None)
+                        .ok_or_else(|| undefined_variable(name, span))?;
+                    self.push(value);
+                }
+                OpCode::DefineGlobal => {
+                    let name = read_string!().to_string();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = read_string!();
+                    if !self.globals.assign(name, None, self.peek(0).clone()) {
+                        return Err(undefined_variable(name, span));
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = read_byte!() as usize;
+                    let value = match &*closure.upvalues[index].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = read_byte!() as usize;
+                    let value = self.peek(0).clone();
+                    match &mut *closure.upvalues[index].borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = read_string!();
+                    let value = match self.pop() {
+                        Literal::VmInstance(instance) => {
+                            let field = instance.fields.borrow().get(name).cloned();
+                            match field {
+                                Some(value) => value,
+                                None => bind_method(&instance.class, name, span, || {
+                                    Literal::VmInstance(instance.clone())
+                                })?,
+                            }
+                        }
+                        object => property(object, name, span)?,
+                    };
+                    self.push(value);
+                }
+                OpCode::SetProperty => {
+                    let name = read_string!();
+                    let value = self.pop();
+                    match self.pop() {
+                        Literal::VmInstance(instance) => {
+                            instance
+                                .fields
+                                .borrow_mut()
+                                .insert(name.to_string(), value.clone());
+                        }
+                        Literal::Instance(instance) => {
+                            instance.set(name.to_string(), value.clone())
+                        }
+                        _ => {
+                            return Err(RuntimeError::new("Only instances have fields.", span)
+                                .with_code("E0205"))
+                        }
+                    }
+                    self.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = read_string!();
+                    let Literal::VmClass(superclass) = self.pop() else {
+                        unreachable!("`super` is always a class once `OpCode::Inherit` has run")
+                    };
+                    let receiver = self.pop();
+                    let method = bind_method(&superclass, name, span, || receiver)?;
+                    self.push(method);
+                }
+                OpCode::Equal => self.binary(Token::EqualEqual, span)?,
+                OpCode::NotEqual => self.binary(Token::BangEqual, span)?,
+                OpCode::Greater => self.binary(Token::Greater, span)?,
+                OpCode::GreaterEqual => self.binary(Token::GreaterEqual, span)?,
+                OpCode::Less => self.binary(Token::Less, span)?,
+                OpCode::LessEqual => self.binary(Token::LessEqual, span)?,
+                OpCode::Add => self.binary(Token::Plus, span)?,
+                OpCode::Subtract => self.binary(Token::Minus, span)?,
+                OpCode::Multiply => self.binary(Token::Star, span)?,
+                OpCode::Divide => self.binary(Token::Slash, span)?,
+                OpCode::Remainder => self.binary(Token::Percent, span)?,
+                OpCode::Power => self.binary(Token::StarStar, span)?,
+                OpCode::IntegerDivide => self.binary(Token::TildeSlash, span)?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push((!value.truthy()).into());
+                }
+                OpCode::Negate => {
+                    let value = self.pop();
+                    self.push(unary(&Token::Minus, value, span)?);
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    print(&mut self.output, &value, span)?;
+                }
+                OpCode::Jump => {
+                    let offset = read_u16!() as usize;
+                    ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = read_u16!() as usize;
+                    if !self.peek(0).truthy() {
+                        ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = read_u16!() as usize;
+                    ip -= offset;
+                }
+                OpCode::Call => {
+                    let argc = read_byte!() as usize;
+                    self.frames.last_mut().expect("a frame is running").ip = ip;
+                    self.call(self.peek(argc).clone(), argc, span)?;
+                    (closure, ip, base) = self.current_frame();
+                }
+                OpCode::Closure => {
+                    let Constant::Function(function) = &chunk.constants[read_u16!() as usize]
+                    else {
+                        unreachable!("closures are made from function constants")
+                    };
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = read_byte!() == 1;
+                        let index = read_byte!() as usize;
+                        upvalues.push(if is_local {
+                            self.capture_upvalue(base + index)
+                        } else {
+                            closure.upvalues[index].clone()
+                        });
+                    }
+                    self.push(Literal::Closure(gc::manage(Closure {
+                        function: function.clone(),
+                        upvalues,
+                    })));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    self.close_upvalues(base);
+                    self.frames.pop();
+                    self.stack.truncate(base);
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+                    self.push(result);
+                    (closure, ip, base) = self.current_frame();
+                }
+                OpCode::Class => {
+                    let name = read_string!().to_string();
+                    self.push(Literal::VmClass(gc::manage(Class::new(name))));
+                }
+                OpCode::Inherit => {
+                    let Literal::VmClass(superclass) = self.peek(1) else {
+                        return Err(RuntimeError::new("Superclass must be a class.", span)
+                            .with_code("E0204"));
+                    };
+                    let Literal::VmClass(subclass) = self.peek(0) else {
+                        unreachable!("the compiler emits the subclass right before inheriting")
+                    };
+                    let methods = superclass.methods.borrow().clone();
+                    subclass.methods.borrow_mut().extend(methods);
+                    self.pop();
+                }
+                OpCode::Method => {
+                    let name = read_string!().to_string();
+                    let Literal::Closure(method) = self.pop() else {
+                        unreachable!("methods are compiled to closures")
+                    };
+                    let Literal::VmClass(class) = self.peek(0) else {
+                        unreachable!("the class stays on the stack while its methods are added")
+                    };
+                    class.methods.borrow_mut().insert(name, method);
+                }
+                OpCode::List => {
+                    let count = read_u16!() as usize;
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.push(elements.into());
+                }
+                OpCode::Map => self.push(BTreeMap::new().into()),
+                OpCode::MapEntry => {
+                    let value = self.pop();
+                    let key = map_key(self.pop(), span)?;
+                    let Literal::Map(entries) = self.peek(0) else {
+                        unreachable!("entries are only added to the map literal below them")
+                    };
+                    entries.borrow_mut().insert(key, value);
+                }
+                OpCode::Index => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    self.push(get_index(object, index, span)?);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    set_index(object, index, value.clone(), span)?;
+                    self.push(value);
+                }
+                OpCode::Interpolate => {
+                    let count = read_u16!() as usize;
+                    let parts = self.stack.split_off(self.stack.len() - count);
+                    let text: String = parts.iter().map(Literal::to_string).collect();
+                    self.push(text.into());
+                }
+            }
+        }
+    }
+
+    /// The closure, instruction pointer and stack base of the frame on top
+    fn current_frame(&self) -> (Rc<Closure>, usize, usize) {
+        let frame = self.frame();
+        (frame.closure.clone(), frame.ip, frame.base)
+    }
+
+    fn binary(&mut self, op: Token, span: Span) -> Result<(), RuntimeError> {
+        let right = self.pop();
+        let left = self.pop();
+        self.push(binary(&op, left, right, span)?);
+        Ok(())
+    }
+
+    /// Calls `callee`, which sits on the stack below its `argc` arguments.
+    /// Closures get a new frame, everything else runs straight away and
+    /// leaves its result in place of the callee.
+    fn call(&mut self, callee: Literal, argc: usize, span: Span) -> Result<(), RuntimeError> {
+        let slot = self.stack.len() - argc - 1;
+        match callee {
+            Literal::Closure(closure) => self.call_closure(closure, argc, span),
+            Literal::BoundMethod(bound) => {
+                self.stack[slot] = bound.receiver.clone();
+                self.call_closure(bound.method.clone(), argc, span)
+            }
+            Literal::VmClass(class) => {
+                self.stack[slot] = Literal::VmInstance(gc::manage(Instance::new(class.clone())));
+                match class.find_method("init") {
+                    Some(init) => self.call_closure(init, argc, span),
+                    None if argc != 0 => Err(wrong_arity(0, argc, span)),
+                    None => Ok(()),
+                }
+            }
+            // natives and the tree-walker's functions and classes, which can
+            // be handed over through globals
+            Literal::Native(_) | Literal::Function(_) | Literal::Class(_) => {
+                let arity = match &callee {
+                    Literal::Native(native) => native.arity,
+                    Literal::Function(function) => function.arity(),
+                    Literal::Class(class) => class.arity(),
+                    _ => unreachable!("matched above"),
+                };
+                if argc != arity {
+                    return Err(wrong_arity(arity, argc, span));
+                }
+                let arguments = self.stack.split_off(slot + 1);
+                // the tree-walker's safe points can't see the VM
+                let mut held = Rooted::new();
+                held.trace(|roots| {
+                    self.trace(roots);
+                    arguments
+                        .iter()
+                        .for_each(|argument| roots.literal(argument));
+                });
+                let result = match callee {
+                    Literal::Native(native) => native
+                        .call(&arguments)
+                        .map_err(|message| RuntimeError::new(message, span))?,
+                    Literal::Function(function) => function.call(arguments, self.output)?,
+                    Literal::Class(class) => class.instantiate(arguments, self.output)?,
+                    _ => unreachable!("matched above"),
+                };
+                self.stack[slot] = result;
+                Ok(())
+            }
+            _ => Err(
+                RuntimeError::new("Can only call functions and classes.", span).with_code("E0203"),
+            ),
+        }
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: Rc<Closure>,
+        argc: usize,
+        span: Span,
+    ) -> Result<(), RuntimeError> {
+        if argc != closure.function.arity {
+            return Err(wrong_arity(closure.function.arity, argc, span));
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(RuntimeError::new("Stack overflow.", span).with_code("E0208"));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - argc - 1,
+        });
+        Ok(())
+    }
+
+    /// The upvalue for the variable in `slot`, shared with any other closure
+    /// that has already captured it
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+        let upvalue = gc::manage(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Moves the variables in `from` and above off the stack and into the
+    /// upvalues that captured them
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= from => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+}
+
+/// The method called `name` on `class`, bound to the receiver
+fn bind_method(
+    class: &Class,
+    name: &str,
+    span: Span,
+    receiver: impl FnOnce() -> Literal,
+) -> Result<Literal, RuntimeError> {
+    let method = class.find_method(name).ok_or_else(|| {
+        RuntimeError::new(format!("Undefined property '{name}'."), span).with_code("E0205")
+    })?;
+    Ok(Literal::BoundMethod(gc::manage(BoundMethod {
+        receiver: receiver(),
+        method,
+    })))
+}
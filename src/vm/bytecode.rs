@@ -0,0 +1,662 @@
+//! The binary format compiled scripts are saved in, so they can be run again
+//! without scanning, parsing and compiling the source.
+//!
+//! All integers are little endian. A file is:
+//!
+//! ```text
+//! magic        b"LOXB"
+//! version      u16, FORMAT_VERSION
+//! source name  string, what runtime errors say the code came from
+//! script       function
+//! ```
+//!
+//! where a string is a u32 byte length followed by UTF-8, and a function is
+//! its name (a string), arity and upvalue count (u32 each), its code (a u32
+//! length and the bytes), its line table and its constant pool. The line
+//! table is a u32 count of runs, each a u32 number of code bytes followed by
+//! the span they were compiled from as four u64s: start, end, line and
+//! column. The constant pool is a u32 count followed by the constants, each a
+//! tag byte then a f64 for `0` (number), a string for `1` and a function for
+//! `2`.
+
+use super::chunk::{Chunk, CompiledFunction, Constant, OpCode, Operands};
+use crate::span::Span;
+use std::{collections::BTreeSet, fmt::Display, rc::Rc};
+
+/// The first bytes of every bytecode file
+pub const MAGIC: [u8; 4] = *b"LOXB";
+
+/// Bumped whenever the format or the meaning of an opcode changes, files with
+/// any other version are rejected
+pub const FORMAT_VERSION: u16 = 1;
+
+const NUMBER: u8 = 0;
+const STRING: u8 = 1;
+const FUNCTION: u8 = 2;
+
+/// Why a bytecode file couldn't be loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytecodeError {
+    /// The file doesn't start with [`MAGIC`]
+    NotBytecode,
+    /// Written for a different [`FORMAT_VERSION`]
+    UnsupportedVersion(u16),
+    /// The file ends part way through
+    Truncated,
+    /// The file is complete but isn't something the compiler could have written
+    Malformed(String),
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BytecodeError::NotBytecode => write!(f, "not a Lox bytecode file"),
+            BytecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "bytecode format version {version} isn't supported, expected {FORMAT_VERSION}; recompile the script"
+            ),
+            BytecodeError::Truncated => write!(f, "the bytecode file is truncated"),
+            BytecodeError::Malformed(reason) => write!(f, "malformed bytecode: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+/// Whether `bytes` look like a bytecode file rather than source code
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Serializes a compiled script, `source_name` is the file it was compiled
+/// from
+pub fn encode(script: &CompiledFunction, source_name: &str) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(FORMAT_VERSION.to_le_bytes());
+    write_string(&mut bytes, source_name);
+    write_function(&mut bytes, script);
+    bytes
+}
+
+/// Loads a script written by [`encode`], returning the name of the source it
+/// was compiled from along with it. The code is checked the way the compiler
+/// would have written it: known opcodes with their operands inside the code,
+/// constants of the right kind, jumps that land on instructions, and local
+/// slots and upvalues that exist whenever they're used.
+pub fn decode(bytes: &[u8]) -> Result<(String, Rc<CompiledFunction>), BytecodeError> {
+    if !is_bytecode(bytes) {
+        return Err(BytecodeError::NotBytecode);
+    }
+    let mut reader = Reader {
+        bytes,
+        position: MAGIC.len(),
+    };
+    let version = reader.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    let source_name = reader.string()?;
+    let script = reader.function()?;
+    if reader.position != bytes.len() {
+        return Err(BytecodeError::Malformed(
+            "unexpected bytes after the script".to_string(),
+        ));
+    }
+    Ok((source_name, script))
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: usize) {
+    let value = u32::try_from(value).expect("the compiler's limits keep sizes within a u32");
+    bytes.extend(value.to_le_bytes());
+}
+
+fn write_string(bytes: &mut Vec<u8>, text: &str) {
+    write_u32(bytes, text.len());
+    bytes.extend(text.as_bytes());
+}
+
+fn write_function(bytes: &mut Vec<u8>, function: &CompiledFunction) {
+    write_string(bytes, &function.name);
+    write_u32(bytes, function.arity);
+    write_u32(bytes, function.upvalue_count);
+
+    let chunk = &function.chunk;
+    write_u32(bytes, chunk.code.len());
+    bytes.extend(&chunk.code);
+
+    // consecutive bytes almost always share a span, so store runs of them
+    let mut runs: Vec<(usize, Span)> = vec![];
+    for span in &chunk.spans {
+        match runs.last_mut() {
+            Some((length, last)) if last == span => *length += 1,
+            _ => runs.push((1, *span)),
+        }
+    }
+    write_u32(bytes, runs.len());
+    for (length, span) in runs {
+        write_u32(bytes, length);
+        for value in [span.start as u64, span.end as u64, span.line, span.column] {
+            bytes.extend(value.to_le_bytes());
+        }
+    }
+
+    write_u32(bytes, chunk.constants.len());
+    for constant in &chunk.constants {
+        match constant {
+            Constant::Number(number) => {
+                bytes.push(NUMBER);
+                bytes.extend(number.to_le_bytes());
+            }
+            Constant::String(text) => {
+                bytes.push(STRING);
+                write_string(bytes, text);
+            }
+            Constant::Function(function) => {
+                bytes.push(FUNCTION);
+                write_function(bytes, function);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8], BytecodeError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(BytecodeError::Truncated)?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], BytecodeError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, BytecodeError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<usize, BytecodeError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn u64(&mut self) -> Result<u64, BytecodeError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let length = self.u32()?;
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_| BytecodeError::Malformed("a string isn't valid UTF-8".to_string()))
+    }
+
+    fn function(&mut self) -> Result<Rc<CompiledFunction>, BytecodeError> {
+        let name = self.string()?;
+        let arity = self.u32()?;
+        let upvalue_count = self.u32()?;
+
+        let length = self.u32()?;
+        let code = self.take(length)?.to_vec();
+
+        let mut spans = vec![];
+        for _ in 0..self.u32()? {
+            let length = self.u32()?;
+            let span = Span {
+                start: self.u64()? as usize,
+                end: self.u64()? as usize,
+                line: self.u64()?,
+                column: self.u64()?,
+            };
+            if spans.len() + length > code.len() {
+                return Err(malformed(&name, "the line table is longer than the code"));
+            }
+            spans.extend(std::iter::repeat_n(span, length));
+        }
+        if spans.len() != code.len() {
+            return Err(malformed(&name, "the line table is shorter than the code"));
+        }
+
+        let mut constants = vec![];
+        for _ in 0..self.u32()? {
+            let constant = match self.u8()? {
+                NUMBER => Constant::Number(f64::from_le_bytes(self.array()?)),
+                STRING => Constant::String(self.string()?.into()),
+                FUNCTION => Constant::Function(self.function()?),
+                tag => return Err(malformed(&name, &format!("unknown constant tag {tag}"))),
+            };
+            constants.push(constant);
+        }
+
+        let function = CompiledFunction {
+            name,
+            arity,
+            upvalue_count,
+            chunk: Chunk {
+                code,
+                constants,
+                spans,
+            },
+        };
+        check_code(&function)?;
+        Ok(Rc::new(function))
+    }
+}
+
+fn malformed(function: &str, reason: &str) -> BytecodeError {
+    let function = if function.is_empty() {
+        "<script>"
+    } else {
+        function
+    };
+    BytecodeError::Malformed(format!("{reason} in {function}"))
+}
+
+/// Makes sure running the code can't take the VM anywhere the compiler's code
+/// couldn't: every instruction is a real opcode whose operands are inside the
+/// code and refer to constants of the right kind, jumps land on instructions,
+/// and every local slot and upvalue used exists when it's used
+fn check_code(function: &CompiledFunction) -> Result<(), BytecodeError> {
+    let chunk = &function.chunk;
+    let name = &function.name;
+    // the opcode, its first operand and its length at the start of each
+    // instruction
+    let mut instructions: Vec<Option<(OpCode, usize, usize)>> = vec![None; chunk.code.len()];
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let op = OpCode::from_byte(chunk.code[offset]).ok_or_else(|| {
+            let reason = format!("unknown opcode {} at {offset}", chunk.code[offset]);
+            malformed(name, &reason)
+        })?;
+        let operands = chunk.code.get(offset + 1..).unwrap_or_default();
+        let missing = || malformed(name, &format!("{op:?} at {offset} is cut off"));
+        let bad = |what: &str| malformed(name, &format!("{op:?} at {offset} has a bad {what}"));
+        let u16_operand = || match operands {
+            [high, low, ..] => Ok(u16::from_be_bytes([*high, *low]) as usize),
+            _ => Err(missing()),
+        };
+
+        let (operand, length) = match op.operands() {
+            Operands::None => (0, 0),
+            Operands::Byte => {
+                let byte = *operands.first().ok_or_else(missing)? as usize;
+                let upvalue = matches!(op, OpCode::GetUpvalue | OpCode::SetUpvalue);
+                if upvalue && byte >= function.upvalue_count {
+                    return Err(bad("upvalue"));
+                }
+                (byte, 1)
+            }
+            Operands::Count | Operands::Jump => (u16_operand()?, 2),
+            Operands::Constant => {
+                let index = u16_operand()?;
+                // everything but `Constant` takes a name
+                let fits = match chunk.constants.get(index) {
+                    Some(Constant::String(_)) => true,
+                    Some(Constant::Number(_)) => op == OpCode::Constant,
+                    _ => false,
+                };
+                if !fits {
+                    return Err(bad("constant"));
+                }
+                (index, 2)
+            }
+            Operands::Closure => {
+                let index = u16_operand()?;
+                let Some(Constant::Function(inner)) = chunk.constants.get(index) else {
+                    return Err(bad("constant"));
+                };
+                let length = 2 + inner.upvalue_count * 2;
+                let captures = operands.get(2..length).ok_or_else(missing)?;
+                for capture in captures.chunks(2) {
+                    let fits = match capture[0] {
+                        // local slots are checked against the stack below
+                        1 => true,
+                        0 => (capture[1] as usize) < function.upvalue_count,
+                        _ => false,
+                    };
+                    if !fits {
+                        return Err(bad("upvalue"));
+                    }
+                }
+                (index, length)
+            }
+        };
+        instructions[offset] = Some((op, operand, 1 + length));
+        offset += 1 + length;
+    }
+
+    // Follow every path through the code tracking how many values are on the
+    // stack and which of them closures have captured. The depth is the same
+    // on every path to an instruction in code the compiler wrote, and captured
+    // slots are only ever removed by closing them. The callee and its
+    // arguments are there to begin with.
+    let mut states: Vec<Option<(usize, BTreeSet<usize>)>> = vec![None; chunk.code.len()];
+    let mut pending = vec![(0, 1 + function.arity, BTreeSet::new())];
+    while let Some((offset, depth, mut captured)) = pending.pop() {
+        let Some(&Some((op, operand, length))) = instructions.get(offset) else {
+            let reason = if offset >= chunk.code.len() {
+                "running the code goes past its end".to_string()
+            } else {
+                format!("a jump lands inside an instruction, at {offset}")
+            };
+            return Err(malformed(name, &reason));
+        };
+        match &mut states[offset] {
+            Some((seen, _)) if *seen != depth => {
+                let reason = format!("the stack depth at {offset} differs between paths");
+                return Err(malformed(name, &reason));
+            }
+            Some((_, seen)) if captured.is_subset(seen) => continue,
+            Some((_, seen)) => {
+                seen.extend(captured);
+                captured = seen.clone();
+            }
+            None => states[offset] = Some((depth, captured.clone())),
+        }
+
+        let bad = |what: &str| malformed(name, &format!("{op:?} at {offset} has a bad {what}"));
+        if matches!(op, OpCode::GetLocal | OpCode::SetLocal) && operand >= depth {
+            return Err(bad("local slot"));
+        }
+        let (pops, pushes) = stack_effect(op, operand);
+        if pops > depth {
+            let reason = format!("{op:?} at {offset} pops more values than the stack holds");
+            return Err(malformed(name, &reason));
+        }
+        let depth = depth - pops;
+        let closes = matches!(op, OpCode::CloseUpvalue | OpCode::Return);
+        if !closes && captured.range(depth..).next().is_some() {
+            let reason = format!("{op:?} at {offset} pops a captured local without closing it");
+            return Err(malformed(name, &reason));
+        }
+        captured.retain(|slot| *slot < depth);
+        if op == OpCode::Closure {
+            let Constant::Function(inner) = &chunk.constants[operand] else {
+                unreachable!("checked above")
+            };
+            for capture in chunk.code[offset + 3..].chunks(2).take(inner.upvalue_count) {
+                // a local function captures the slot it's about to be put in
+                // so it can call itself
+                let slot = capture[1] as usize;
+                if capture[0] == 1 {
+                    if slot > depth {
+                        return Err(bad("local slot"));
+                    }
+                    captured.insert(slot);
+                }
+            }
+        }
+        let depth = depth + pushes;
+
+        let next = offset + length;
+        let targets = match op {
+            OpCode::Return => vec![],
+            OpCode::Jump => vec![next + operand],
+            OpCode::JumpIfFalse => vec![next, next + operand],
+            OpCode::Loop => match next.checked_sub(operand) {
+                Some(target) => vec![target],
+                None => return Err(bad("jump")),
+            },
+            _ => vec![next],
+        };
+        for target in targets {
+            pending.push((target, depth, captured.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// How many values `op` pops off the stack and how many it pushes back,
+/// `operand` is its first operand
+fn stack_effect(op: OpCode, operand: usize) -> (usize, usize) {
+    match op {
+        OpCode::Constant
+        | OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::GetLocal
+        | OpCode::GetGlobal
+        | OpCode::GetUpvalue
+        | OpCode::Closure
+        | OpCode::Class
+        | OpCode::Map => (0, 1),
+        OpCode::Pop
+        | OpCode::DefineGlobal
+        | OpCode::Print
+        | OpCode::CloseUpvalue
+        | OpCode::Return => (1, 0),
+        OpCode::SetLocal
+        | OpCode::SetGlobal
+        | OpCode::SetUpvalue
+        | OpCode::GetProperty
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::JumpIfFalse => (1, 1),
+        OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Equal
+        | OpCode::NotEqual
+        | OpCode::Greater
+        | OpCode::GreaterEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Remainder
+        | OpCode::Power
+        | OpCode::IntegerDivide
+        | OpCode::Inherit
+        | OpCode::Method
+        | OpCode::Index => (2, 1),
+        OpCode::MapEntry | OpCode::SetIndex => (3, 1),
+        OpCode::Jump | OpCode::Loop => (0, 0),
+        // the callee and its arguments
+        OpCode::Call => (operand + 1, 1),
+        OpCode::List | OpCode::Interpolate => (operand, 1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode, BytecodeError, FORMAT_VERSION, MAGIC};
+    use crate::{
+        interpreter::{CapturedOutput, Interpreter},
+        vm::chunk::{CompiledFunction, Constant, OpCode},
+    };
+    use std::rc::Rc;
+
+    const PROGRAM: &str = "fun counter() {
+        var count = 0;
+        fun increment() { count = count + 1; return count; }
+        return increment;
+    }
+    var next = counter();
+    next();
+    print \"${next()} ${-0.5} ${[1, 2]}\";";
+
+    fn compile(src: &str) -> Rc<CompiledFunction> {
+        Interpreter::new()
+            .with_stderr(CapturedOutput::new())
+            .compile_script(src)
+            .expect("the program compiles")
+    }
+
+    #[test]
+    fn round_trips() {
+        let script = compile(PROGRAM);
+        let (name, decoded) = decode(&encode(&script, "counter.lox")).unwrap();
+        assert_eq!(name, "counter.lox");
+        assert_eq!(decoded, script);
+    }
+
+    #[test]
+    fn runs_like_the_source() {
+        let output = CapturedOutput::new();
+        let bytes = encode(&compile(PROGRAM), "counter.lox");
+        Interpreter::new()
+            .with_stdout(output.clone())
+            .run_bytecode(&bytes)
+            .unwrap();
+        assert_eq!(output.contents(), "2 -0.5 [1, 2]\n");
+    }
+
+    #[test]
+    fn runtime_errors_point_at_the_source() {
+        let errors = CapturedOutput::new();
+        let bytes = encode(&compile("var a = 1;\nprint a + nil;"), "main.lox");
+        let result = Interpreter::new()
+            .with_stdout(CapturedOutput::new())
+            .with_stderr(errors.clone())
+            .run_bytecode(&bytes);
+        assert_eq!(result.unwrap_err().exit_code(), 70);
+        assert!(errors.contents().contains(" --> main.lox:2:7\n"));
+    }
+
+    #[test]
+    fn rejects_every_truncation() {
+        let bytes = encode(&compile(PROGRAM), "counter.lox");
+        assert_eq!(decode(&bytes[..2]), Err(BytecodeError::NotBytecode));
+        for length in MAGIC.len()..bytes.len() {
+            assert_eq!(
+                decode(&bytes[..length]).map(|_| ()),
+                Err(BytecodeError::Truncated),
+                "cut at {length}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = encode(&compile("print 1;"), "a.lox");
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            decode(&bytes).map(|_| ()),
+            Err(BytecodeError::UnsupportedVersion(FORMAT_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_code() {
+        let mut script = CompiledFunction::default();
+        script.chunk.write(200, Default::default());
+        let error = decode(&encode(&script, "a.lox")).map(|_| ()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "malformed bytecode: unknown opcode 200 at 0 in <script>"
+        );
+
+        let mut bytes = encode(&compile("print 1;"), "a.lox");
+        bytes.push(0);
+        assert!(matches!(decode(&bytes), Err(BytecodeError::Malformed(_))));
+    }
+
+    /// Why `script` is rejected, once written to a file
+    fn rejection(script: &CompiledFunction) -> String {
+        let bytes = encode(script, "a.lox");
+        decode(&bytes).map(|_| ()).unwrap_err().to_string()
+    }
+
+    fn script(code: &[u8]) -> CompiledFunction {
+        let mut script = CompiledFunction::default();
+        for byte in code
+            .iter()
+            .chain(&[OpCode::Nil as u8, OpCode::Return as u8])
+        {
+            script.chunk.write(*byte, Default::default());
+        }
+        script
+    }
+
+    #[test]
+    fn rejects_bad_local_slots() {
+        let pop = OpCode::Pop as u8;
+        // only the script itself is on the stack, in slot 0
+        assert_eq!(
+            rejection(&script(&[OpCode::GetLocal as u8, 1, pop])),
+            "malformed bytecode: GetLocal at 0 has a bad local slot in <script>"
+        );
+        assert_eq!(
+            rejection(&script(&[pop, pop])),
+            "malformed bytecode: Pop at 1 pops more values than the stack holds in <script>"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_upvalues() {
+        assert_eq!(
+            rejection(&script(&[OpCode::GetUpvalue as u8, 0, OpCode::Pop as u8])),
+            "malformed bytecode: GetUpvalue at 0 has a bad upvalue in <script>"
+        );
+
+        let closure = |is_local: u8, index: u8| {
+            let inner = CompiledFunction {
+                name: "f".to_string(),
+                upvalue_count: 1,
+                ..script(&[])
+            };
+            let code = [
+                OpCode::Closure as u8,
+                0,
+                0,
+                is_local,
+                index,
+                OpCode::Pop as u8,
+            ];
+            let mut outer = script(&code);
+            outer.chunk.add_constant(Constant::Function(Rc::new(inner)));
+            rejection(&outer)
+        };
+        assert_eq!(
+            closure(0, 0),
+            "malformed bytecode: Closure at 0 has a bad upvalue in <script>"
+        );
+        assert_eq!(
+            closure(2, 0),
+            "malformed bytecode: Closure at 0 has a bad upvalue in <script>"
+        );
+        assert_eq!(
+            closure(1, 2),
+            "malformed bytecode: Closure at 0 has a bad local slot in <script>"
+        );
+        // the closure captures the slot it's put in, then drops it without
+        // closing the upvalue
+        assert_eq!(
+            closure(1, 1),
+            "malformed bytecode: Pop at 5 pops a captured local without closing it in <script>"
+        );
+    }
+
+    #[test]
+    fn rejects_jumps_between_instructions() {
+        let jump = script(&[
+            OpCode::Jump as u8,
+            0,
+            1,
+            OpCode::GetLocal as u8,
+            0,
+            OpCode::Pop as u8,
+        ]);
+        assert_eq!(
+            rejection(&jump),
+            "malformed bytecode: a jump lands inside an instruction, at 4 in <script>"
+        );
+        let past_the_end = script(&[OpCode::Jump as u8, 0, 9]);
+        assert_eq!(
+            rejection(&past_the_end),
+            "malformed bytecode: running the code goes past its end in <script>"
+        );
+    }
+}
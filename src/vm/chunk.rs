@@ -0,0 +1,283 @@
+use crate::span::Span;
+use std::rc::Rc;
+
+/// One byte instructions for the VM. Operands are stored in the bytes straight
+/// after the opcode, the comment on each opcode lists them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    /// constant index: u16
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    /// stack slot: u8
+    GetLocal,
+    /// stack slot: u8
+    SetLocal,
+    /// name constant: u16
+    GetGlobal,
+    /// name constant: u16
+    DefineGlobal,
+    /// name constant: u16
+    SetGlobal,
+    /// upvalue index: u8
+    GetUpvalue,
+    /// upvalue index: u8
+    SetUpvalue,
+    /// name constant: u16
+    GetProperty,
+    /// name constant: u16
+    SetProperty,
+    /// name constant: u16
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+    IntegerDivide,
+    Not,
+    Negate,
+    Print,
+    /// forward offset: u16
+    Jump,
+    /// forward offset: u16, leaves the condition on the stack
+    JumpIfFalse,
+    /// backward offset: u16
+    Loop,
+    /// argument count: u8
+    Call,
+    /// function constant: u16, then an (is local: u8, index: u8) pair for each
+    /// of the function's upvalues
+    Closure,
+    CloseUpvalue,
+    Return,
+    /// name constant: u16
+    Class,
+    Inherit,
+    /// name constant: u16
+    Method,
+    /// element count: u16
+    List,
+    /// Pushes an empty map, `MapEntry` fills it in
+    Map,
+    /// Adds the key and value on top of the stack to the map below them
+    MapEntry,
+    Index,
+    SetIndex,
+    /// part count: u16
+    Interpolate,
+}
+
+impl OpCode {
+    /// Every opcode, in the order of their byte values
+    const ALL: [OpCode; 47] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Remainder,
+        OpCode::Power,
+        OpCode::IntegerDivide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+        OpCode::List,
+        OpCode::Map,
+        OpCode::MapEntry,
+        OpCode::Index,
+        OpCode::SetIndex,
+        OpCode::Interpolate,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        Self::ALL.get(byte as usize).copied()
+    }
+
+    /// The shape of the operands that follow the opcode
+    pub fn operands(self) -> Operands {
+        match self {
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Class
+            | OpCode::Method => Operands::Constant,
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call => Operands::Byte,
+            OpCode::List | OpCode::Interpolate => Operands::Count,
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => Operands::Jump,
+            OpCode::Closure => Operands::Closure,
+            _ => Operands::None,
+        }
+    }
+}
+
+/// What follows an opcode in the code, see [`OpCode::operands`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operands {
+    None,
+    /// A slot, upvalue index or argument count
+    Byte,
+    /// A u16 index into the constants
+    Constant,
+    /// A u16 element or part count
+    Count,
+    /// A u16 offset, backwards for `Loop`
+    Jump,
+    /// A u16 function constant and a pair of bytes per upvalue
+    Closure,
+}
+
+/// The values that can be known when compiling, everything else is created
+/// by the VM while it runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<CompiledFunction>),
+}
+
+/// A function's bytecode along with the constants it refers to
+#[derive(Debug, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Constant>,
+    /// The source each byte of `code` was compiled from, for runtime errors
+    pub spans: Vec<Span>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, byte: u8, span: Span) {
+        self.code.push(byte);
+        self.spans.push(span);
+    }
+
+    pub fn write_op(&mut self, op: OpCode, span: Span) {
+        self.write(op as u8, span);
+    }
+
+    pub fn write_u16(&mut self, value: u16, span: Span) {
+        for byte in value.to_be_bytes() {
+            self.write(byte, span);
+        }
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    /// Index of `constant` in the pool, reusing an equal constant if there is
+    /// one so a name used many times is only stored once
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        let existing = self
+            .constants
+            .iter()
+            .position(|other| match (other, &constant) {
+                // by bits so 0 and -0 stay apart
+                (Constant::Number(other), Constant::Number(number)) => {
+                    other.to_bits() == number.to_bits()
+                }
+                (Constant::String(other), Constant::String(text)) => other == text,
+                _ => false,
+            });
+        existing.unwrap_or_else(|| {
+            self.constants.push(constant);
+            self.constants.len() - 1
+        })
+    }
+}
+
+/// What a function declaration compiles to. The script itself is one too,
+/// with no name and no parameters.
+#[derive(Debug, Default, PartialEq)]
+pub struct CompiledFunction {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+impl CompiledFunction {
+    /// What the function is called in messages, the script is `<script>`
+    pub fn display_name(&self) -> &str {
+        if self.name.is_empty() {
+            "<script>"
+        } else {
+            &self.name
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Chunk, Constant, OpCode};
+    use crate::span::Span;
+
+    #[test]
+    fn opcodes_round_trip_through_bytes() {
+        for (byte, op) in OpCode::ALL.iter().enumerate() {
+            assert_eq!(*op as u8 as usize, byte);
+            assert_eq!(OpCode::from_byte(byte as u8), Some(*op));
+        }
+        assert_eq!(OpCode::from_byte(OpCode::ALL.len() as u8), None);
+    }
+
+    #[test]
+    fn constants_are_deduplicated() {
+        let mut chunk = Chunk::default();
+        assert_eq!(chunk.add_constant(Constant::String("a".into())), 0);
+        assert_eq!(chunk.add_constant(Constant::Number(1.0)), 1);
+        assert_eq!(chunk.add_constant(Constant::String("a".into())), 0);
+        assert_eq!(chunk.add_constant(Constant::Number(0.0)), 2);
+        assert_eq!(chunk.add_constant(Constant::Number(-0.0)), 3);
+        chunk.write_u16(0x1234, Span::default());
+        assert_eq!(chunk.read_u16(0), 0x1234);
+    }
+}
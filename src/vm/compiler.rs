@@ -0,0 +1,677 @@
+use super::chunk::{Chunk, CompiledFunction, Constant, OpCode};
+use crate::{
+    diagnostic::Diagnostic,
+    literal::Literal,
+    parser::{
+        expression::{Expr, ExprKind},
+        statement::{FunctionDecl, Stmt, StmtKind},
+    },
+    scanner::Token,
+    span::Span,
+    Context,
+};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    /// Captured locals are moved off the stack when they go out of scope
+    captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct UpvalueRef {
+    /// A local of the enclosing function, rather than one of its upvalues
+    is_local: bool,
+    index: u8,
+}
+
+struct Loop {
+    /// Locals deeper than this are popped by `break` and `continue`
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// A function part way through being compiled
+struct FunctionState {
+    function: CompiledFunction,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+}
+
+impl FunctionState {
+    fn new(name: &str, kind: FunctionKind) -> Self {
+        // slot 0 holds the function being called, or `this` in methods
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        Self {
+            function: CompiledFunction {
+                name: name.to_string(),
+                ..Default::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                captured: false,
+            }],
+            upvalues: vec![],
+            scope_depth: 0,
+            loops: vec![],
+        }
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<usize> {
+        self.locals.iter().rposition(|local| local.name == name)
+    }
+}
+
+/// Compiles a resolved program into the function the VM runs as the script.
+/// Programs bigger than the bytecode can describe, like a function with more
+/// than 256 locals, are reported to `ctx`.
+pub fn compile(program: &[Stmt], ctx: &mut Context) -> Rc<CompiledFunction> {
+    let mut compiler = Compiler::new(ctx);
+    for statement in program {
+        compiler.statement(statement);
+    }
+    let end = program.last().map_or_else(Span::default, |last| last.span);
+    compiler.emit(OpCode::Nil, end);
+    compiler.emit(OpCode::Return, end);
+    compiler.finish().0.into()
+}
+
+/// Compiles a single expression into a script that returns its value
+pub fn compile_expression(expr: &Expr, ctx: &mut Context) -> Rc<CompiledFunction> {
+    let mut compiler = Compiler::new(ctx);
+    compiler.expression(expr);
+    compiler.emit(OpCode::Return, expr.span);
+    compiler.finish().0.into()
+}
+
+struct Compiler<'a> {
+    /// The function being compiled last, preceded by the functions it is
+    /// nested in
+    functions: Vec<FunctionState>,
+    ctx: &'a mut Context,
+}
+
+impl<'a> Compiler<'a> {
+    fn new(ctx: &'a mut Context) -> Self {
+        Self {
+            functions: vec![FunctionState::new("", FunctionKind::Script)],
+            ctx,
+        }
+    }
+
+    fn error(&mut self, message: &str, span: Span) {
+        self.ctx.error(Diagnostic::new("E0300", message, span));
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("there is always a function being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().function.chunk
+    }
+
+    /// Finishes the innermost function, returning it with the upvalues it
+    /// captures from the function it is nested in
+    fn finish(&mut self) -> (CompiledFunction, Vec<UpvalueRef>) {
+        let state = self
+            .functions
+            .pop()
+            .expect("there is always a function being compiled");
+        let mut function = state.function;
+        function.upvalue_count = state.upvalues.len();
+        (function, state.upvalues)
+    }
+
+    fn emit(&mut self, op: OpCode, span: Span) {
+        self.chunk().write_op(op, span);
+    }
+
+    fn emit_byte(&mut self, byte: u8, span: Span) {
+        self.chunk().write(byte, span);
+    }
+
+    fn emit_u16(&mut self, value: u16, span: Span) {
+        self.chunk().write_u16(value, span);
+    }
+
+    fn constant(&mut self, constant: Constant, span: Span) -> u16 {
+        let index = self.chunk().add_constant(constant);
+        u16::try_from(index).unwrap_or_else(|_| {
+            self.error("Too many constants in one chunk.", span);
+            0
+        })
+    }
+
+    fn emit_constant(&mut self, constant: Constant, span: Span) {
+        let index = self.constant(constant, span);
+        self.emit(OpCode::Constant, span);
+        self.emit_u16(index, span);
+    }
+
+    /// An instruction whose operand is the name as a string constant
+    fn emit_named(&mut self, op: OpCode, name: &str, span: Span) {
+        let index = self.constant(Constant::String(name.into()), span);
+        self.emit(op, span);
+        self.emit_u16(index, span);
+    }
+
+    /// A u16 count operand, for lists and interpolations
+    fn emit_count(&mut self, op: OpCode, count: usize, span: Span) {
+        let count = u16::try_from(count).unwrap_or_else(|_| {
+            self.error("Too many elements in one literal.", span);
+            0
+        });
+        self.emit(op, span);
+        self.emit_u16(count, span);
+    }
+
+    /// Emits a jump with a placeholder offset, returning where the offset is
+    /// so it can be patched once the target is known
+    fn emit_jump(&mut self, op: OpCode, span: Span) -> usize {
+        self.emit(op, span);
+        self.emit_u16(u16::MAX, span);
+        self.chunk().code.len() - 2
+    }
+
+    /// Points the jump at `offset` to the next instruction
+    fn patch_jump(&mut self, offset: usize) {
+        let distance = self.chunk().code.len() - offset - 2;
+        let distance = u16::try_from(distance).unwrap_or_else(|_| {
+            let span = self.chunk().spans[offset];
+            self.error("Too much code to jump over.", span);
+            0
+        });
+        self.chunk().code[offset..offset + 2].copy_from_slice(&distance.to_be_bytes());
+    }
+
+    fn emit_loop(&mut self, start: usize, span: Span) {
+        self.emit(OpCode::Loop, span);
+        // the offset is counted from after the operand
+        let distance = self.chunk().code.len() + 2 - start;
+        let distance = u16::try_from(distance).unwrap_or_else(|_| {
+            self.error("Loop body too large.", span);
+            0
+        });
+        self.emit_u16(distance, span);
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        let state = self.current();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        while let Some(local) = self.current().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            let op = if local.captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.emit(op, span);
+            self.current().locals.pop();
+        }
+    }
+
+    /// Pops the locals inside the innermost loop without forgetting them, for
+    /// `break` and `continue`
+    fn pop_loop_locals(&mut self, span: Span) {
+        let state = self.current();
+        let depth = state
+            .loops
+            .last()
+            .expect("the parser only allows break and continue inside loops")
+            .scope_depth;
+        let ops: Vec<_> = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| {
+                if local.captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect();
+        for op in ops {
+            self.emit(op, span);
+        }
+    }
+
+    fn add_local(&mut self, name: &str, span: Span) {
+        if self.current().locals.len() > u8::MAX as usize {
+            self.error("Too many local variables in function.", span);
+            return;
+        }
+        let depth = self.current().scope_depth;
+        self.current().locals.push(Local {
+            name: name.to_string(),
+            depth,
+            captured: false,
+        });
+    }
+
+    /// Declares a variable before its value is compiled, so functions and
+    /// classes can refer to themselves. Globals don't need declaring.
+    fn declare(&mut self, name: &str, span: Span) {
+        if self.current().scope_depth > 0 {
+            self.add_local(name, span);
+        }
+    }
+
+    /// Stores the value on top of the stack in the variable just declared.
+    /// A local's value already sits in its slot.
+    fn define(&mut self, name: &str, span: Span) {
+        if self.current().scope_depth == 0 {
+            self.emit_named(OpCode::DefineGlobal, name, span);
+        }
+    }
+
+    fn add_upvalue(&mut self, level: usize, upvalue: UpvalueRef, span: Span) -> u8 {
+        let upvalues = &mut self.functions[level].upvalues;
+        if let Some(index) = upvalues.iter().position(|existing| *existing == upvalue) {
+            return index as u8;
+        }
+        if upvalues.len() > u8::MAX as usize {
+            self.error("Too many closure variables in function.", span);
+            return 0;
+        }
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    /// Finds `name` in the functions enclosing the one at `level`, capturing
+    /// it in every function in between
+    fn resolve_upvalue(&mut self, level: usize, name: &str, span: Span) -> Option<u8> {
+        let enclosing = level.checked_sub(1)?;
+        if let Some(local) = self.functions[enclosing].resolve_local(name) {
+            self.functions[enclosing].locals[local].captured = true;
+            let upvalue = UpvalueRef {
+                is_local: true,
+                index: local as u8,
+            };
+            return Some(self.add_upvalue(level, upvalue, span));
+        }
+        let index = self.resolve_upvalue(enclosing, name, span)?;
+        let upvalue = UpvalueRef {
+            is_local: false,
+            index,
+        };
+        Some(self.add_upvalue(level, upvalue, span))
+    }
+
+    /// Reads or, when `set` is true, assigns the variable called `name`
+    fn variable(&mut self, name: &str, set: bool, span: Span) {
+        let (op, operand) = if let Some(slot) = self.current().resolve_local(name) {
+            let op = if set {
+                OpCode::SetLocal
+            } else {
+                OpCode::GetLocal
+            };
+            (op, slot as u8)
+        } else if let Some(index) = self.resolve_upvalue(self.functions.len() - 1, name, span) {
+            let op = if set {
+                OpCode::SetUpvalue
+            } else {
+                OpCode::GetUpvalue
+            };
+            (op, index)
+        } else {
+            let op = if set {
+                OpCode::SetGlobal
+            } else {
+                OpCode::GetGlobal
+            };
+            self.emit_named(op, name, span);
+            return;
+        };
+        self.emit(op, span);
+        self.emit_byte(operand, span);
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        let span = statement.span;
+        match &statement.kind {
+            StmtKind::Print(expr) => {
+                self.expression(expr);
+                self.emit(OpCode::Print, span);
+            }
+            StmtKind::Expression(expr) => {
+                self.expression(expr);
+                self.emit(OpCode::Pop, span);
+            }
+            StmtKind::Var(name, value) => {
+                self.expression(value);
+                self.declare(name, span);
+                self.define(name, span);
+            }
+            StmtKind::Function(declaration) => {
+                self.declare(&declaration.name, span);
+                self.function(declaration, FunctionKind::Function, span);
+                self.define(&declaration.name, span);
+            }
+            StmtKind::Class {
+                name,
+                superclass,
+                methods,
+            } => self.class(name, superclass.as_ref(), methods, span),
+            StmtKind::Return(value) => {
+                match value {
+                    Some(value) => self.expression(value),
+                    // initializers always hand back `this`, the resolver
+                    // makes sure they don't try to return anything else
+                    None if self.current().kind == FunctionKind::Initializer => {
+                        self.emit(OpCode::GetLocal, span);
+                        self.emit_byte(0, span);
+                    }
+                    None => self.emit(OpCode::Nil, span),
+                }
+                self.emit(OpCode::Return, span);
+            }
+            StmtKind::Block(statements) => {
+                self.begin_scope();
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.end_scope(span);
+            }
+            StmtKind::If {
+                condition,
+                then_stmt,
+                else_stmt,
+            } => {
+                self.expression(condition);
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+                self.statement(then_stmt);
+                let end_jump = self.emit_jump(OpCode::Jump, span);
+                self.patch_jump(else_jump);
+                self.emit(OpCode::Pop, span);
+                if let Some(else_stmt) = else_stmt {
+                    self.statement(else_stmt);
+                }
+                self.patch_jump(end_jump);
+            }
+            StmtKind::While {
+                condition,
+                body,
+                increment,
+            } => {
+                let start = self.chunk().code.len();
+                self.expression(condition);
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+
+                let scope_depth = self.current().scope_depth;
+                self.current().loops.push(Loop {
+                    scope_depth,
+                    breaks: vec![],
+                    continues: vec![],
+                });
+                self.statement(body);
+                let Loop {
+                    breaks, continues, ..
+                } = self.current().loops.pop().expect("pushed above");
+
+                for jump in continues {
+                    self.patch_jump(jump);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                    self.emit(OpCode::Pop, span);
+                }
+                self.emit_loop(start, span);
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop, span);
+                // `break` skips the pop, the condition isn't on the stack
+                for jump in breaks {
+                    self.patch_jump(jump);
+                }
+            }
+            StmtKind::Break => {
+                self.pop_loop_locals(span);
+                let jump = self.emit_jump(OpCode::Jump, span);
+                let innermost = self.current().loops.last_mut();
+                innermost.expect("checked above").breaks.push(jump);
+            }
+            StmtKind::Continue => {
+                self.pop_loop_locals(span);
+                let jump = self.emit_jump(OpCode::Jump, span);
+                let innermost = self.current().loops.last_mut();
+                innermost.expect("checked above").continues.push(jump);
+            }
+        }
+    }
+
+    /// Compiles the function and emits the instruction that creates it
+    fn function(&mut self, declaration: &FunctionDecl, kind: FunctionKind, span: Span) {
+        let mut state = FunctionState::new(&declaration.name, kind);
+        state.function.arity = declaration.params.len();
+        // parameters live in their own scope, so they are never mistaken for
+        // globals
+        state.scope_depth = 1;
+        self.functions.push(state);
+        for param in &declaration.params {
+            self.add_local(param, span);
+        }
+        for statement in &declaration.body {
+            self.statement(statement);
+        }
+        if kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal, span);
+            self.emit_byte(0, span);
+        } else {
+            self.emit(OpCode::Nil, span);
+        }
+        self.emit(OpCode::Return, span);
+
+        let (function, upvalues) = self.finish();
+        let index = self.constant(Constant::Function(function.into()), span);
+        self.emit(OpCode::Closure, span);
+        self.emit_u16(index, span);
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local.into(), span);
+            self.emit_byte(upvalue.index, span);
+        }
+    }
+
+    fn class(
+        &mut self,
+        name: &str,
+        superclass: Option<&Expr>,
+        methods: &[Rc<FunctionDecl>],
+        span: Span,
+    ) {
+        self.declare(name, span);
+        self.emit_named(OpCode::Class, name, span);
+        self.define(name, span);
+
+        if let Some(superclass) = superclass {
+            self.expression(superclass);
+            // methods find the superclass through a local called `super`
+            self.begin_scope();
+            self.add_local("super", span);
+            self.variable(name, false, span);
+            self.emit(OpCode::Inherit, superclass.span);
+        }
+
+        self.variable(name, false, span);
+        for method in methods {
+            let kind = if method.name == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind, span);
+            self.emit_named(OpCode::Method, &method.name, span);
+        }
+        self.emit(OpCode::Pop, span);
+
+        if superclass.is_some() {
+            self.end_scope(span);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        let span = expr.span;
+        match &expr.kind {
+            ExprKind::Grouping(expr) => self.expression(expr),
+            ExprKind::Literal(literal) => match literal {
+                Literal::Nil => self.emit(OpCode::Nil, span),
+                Literal::True => self.emit(OpCode::True, span),
+                Literal::False => self.emit(OpCode::False, span),
+                Literal::Number(number) => self.emit_constant(Constant::Number(*number), span),
+                Literal::String(text) => self.emit_constant(Constant::String(text.clone()), span),
+                literal => unreachable!(
+                    "only numbers, strings, booleans and nil appear in source, got {literal}"
+                ),
+            },
+            ExprKind::Unary { op, expr } => {
+                self.expression(expr);
+                let op = match op {
+                    Token::Bang => OpCode::Not,
+                    Token::Minus => OpCode::Negate,
+                    op => unreachable!("invalid unary operator {}", op.token_type()),
+                };
+                self.emit(op, span);
+            }
+            ExprKind::Binary { left, op, right } => {
+                self.expression(left);
+                self.expression(right);
+                let op = match op {
+                    Token::Plus => OpCode::Add,
+                    Token::Minus => OpCode::Subtract,
+                    Token::Star => OpCode::Multiply,
+                    Token::Slash => OpCode::Divide,
+                    Token::Percent => OpCode::Remainder,
+                    Token::TildeSlash => OpCode::IntegerDivide,
+                    Token::StarStar => OpCode::Power,
+                    Token::Less => OpCode::Less,
+                    Token::LessEqual => OpCode::LessEqual,
+                    Token::Greater => OpCode::Greater,
+                    Token::GreaterEqual => OpCode::GreaterEqual,
+                    Token::EqualEqual => OpCode::Equal,
+                    Token::BangEqual => OpCode::NotEqual,
+                    op => unreachable!("invalid binary operator {}", op.token_type()),
+                };
+                self.emit(op, span);
+            }
+            ExprKind::Logical { left, op, right } => {
+                self.expression(left);
+                match op {
+                    Token::And => {
+                        let end_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                        self.emit(OpCode::Pop, span);
+                        self.expression(right);
+                        self.patch_jump(end_jump);
+                    }
+                    Token::Or => {
+                        let else_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                        let end_jump = self.emit_jump(OpCode::Jump, span);
+                        self.patch_jump(else_jump);
+                        self.emit(OpCode::Pop, span);
+                        self.expression(right);
+                        self.patch_jump(end_jump);
+                    }
+                    op => unreachable!("invalid logical operator {}", op.token_type()),
+                }
+            }
+            ExprKind::Variable { name, .. } => self.variable(name, false, span),
+            ExprKind::Assign { name, value, .. } => {
+                self.expression(value);
+                self.variable(name, true, span);
+            }
+            ExprKind::Call { callee, arguments } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                // the parser stops at 255 arguments
+                self.emit(OpCode::Call, span);
+                self.emit_byte(arguments.len() as u8, span);
+            }
+            ExprKind::Get { object, name } => {
+                self.expression(object);
+                self.emit_named(OpCode::GetProperty, name, span);
+            }
+            ExprKind::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(value);
+                self.emit_named(OpCode::SetProperty, name, span);
+            }
+            ExprKind::This { .. } => self.variable("this", false, span),
+            ExprKind::Super { method, .. } => {
+                self.variable("this", false, span);
+                self.variable("super", false, span);
+                self.emit_named(OpCode::GetSuper, method, span);
+            }
+            ExprKind::Interpolation(parts) => {
+                for part in parts {
+                    self.expression(part);
+                }
+                self.emit_count(OpCode::Interpolate, parts.len(), span);
+            }
+            ExprKind::List(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+                self.emit_count(OpCode::List, elements.len(), span);
+            }
+            ExprKind::Map(entries) => {
+                self.emit(OpCode::Map, span);
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                    // bad keys are reported at the key
+                    self.emit(OpCode::MapEntry, key.span);
+                }
+            }
+            ExprKind::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+                self.emit(OpCode::Index, span);
+            }
+            ExprKind::SetIndex {
+                object,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.emit(OpCode::SetIndex, span);
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use super::chunk::{Chunk, CompiledFunction, Constant, OpCode, Operands};
+use crate::literal::Literal;
+use std::fmt::Write;
+
+/// A listing of `function`'s bytecode followed by the bytecode of every
+/// function declared inside it, in the order they appear in the constants
+pub fn disassemble(function: &CompiledFunction) -> String {
+    let mut listing = disassemble_chunk(&function.chunk, function.display_name());
+    for constant in &function.chunk.constants {
+        if let Constant::Function(inner) = constant {
+            listing.push('\n');
+            listing.push_str(&disassemble(inner));
+        }
+    }
+    listing
+}
+
+/// Every instruction in `chunk` under a `== name ==` header, one per line
+pub fn disassemble_chunk(chunk: &Chunk, name: &str) -> String {
+    let mut listing = format!("== {name} ==\n");
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(chunk, offset, &mut listing);
+    }
+    listing
+}
+
+/// Writes the instruction at `offset` to `out` as its offset, source line
+/// (`|` when it's the same as the previous instruction's), opcode name and
+/// operands. Returns the offset of the next instruction.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    let line = chunk.spans[offset].line;
+    if offset > 0 && chunk.spans[offset - 1].line == line {
+        let _ = write!(out, "{offset:04}    | ");
+    } else {
+        let _ = write!(out, "{offset:04} {line:4} ");
+    }
+
+    let Some(op) = OpCode::from_byte(chunk.code[offset]) else {
+        let _ = writeln!(out, "Unknown opcode {}", chunk.code[offset]);
+        return offset + 1;
+    };
+    let name = op_name(op);
+    match op.operands() {
+        Operands::Constant => {
+            let index = chunk.read_u16(offset + 1);
+            let constant = show_constant(&chunk.constants[index as usize]);
+            let _ = writeln!(out, "{name:<16} {index:4} '{constant}'");
+            offset + 3
+        }
+        Operands::Byte => {
+            let _ = writeln!(out, "{name:<16} {:4}", chunk.code[offset + 1]);
+            offset + 2
+        }
+        Operands::Count => {
+            let _ = writeln!(out, "{name:<16} {:4}", chunk.read_u16(offset + 1));
+            offset + 3
+        }
+        Operands::Jump => {
+            let jump = chunk.read_u16(offset + 1) as usize;
+            let target = if op == OpCode::Loop {
+                offset + 3 - jump
+            } else {
+                offset + 3 + jump
+            };
+            let _ = writeln!(out, "{name:<16} {offset:4} -> {target}");
+            offset + 3
+        }
+        Operands::Closure => {
+            let index = chunk.read_u16(offset + 1);
+            let constant = &chunk.constants[index as usize];
+            let _ = writeln!(out, "{name:<16} {index:4} {}", show_constant(constant));
+            let Constant::Function(function) = constant else {
+                return offset + 3;
+            };
+            let mut offset = offset + 3;
+            for _ in 0..function.upvalue_count {
+                let kind = if chunk.code[offset] == 1 {
+                    "local"
+                } else {
+                    "upvalue"
+                };
+                let _ = writeln!(
+                    out,
+                    "{offset:04}    |                     {kind} {}",
+                    chunk.code[offset + 1]
+                );
+                offset += 2;
+            }
+            offset
+        }
+        Operands::None => {
+            let _ = writeln!(out, "{name}");
+            offset + 1
+        }
+    }
+}
+
+/// clox's name for an opcode, `GetLocal` is `OP_GET_LOCAL`
+fn op_name(op: OpCode) -> String {
+    let mut name = "OP".to_string();
+    for c in format!("{op:?}").chars() {
+        if c.is_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
+    }
+    name
+}
+
+fn show_constant(constant: &Constant) -> String {
+    match constant {
+        Constant::Number(number) => Literal::Number(*number).to_string(),
+        Constant::String(text) => text.to_string(),
+        Constant::Function(function) => format!("<fn {}>", function.display_name()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{disassemble, op_name};
+    use crate::{
+        interpreter::{CapturedOutput, Interpreter},
+        vm::chunk::OpCode,
+    };
+
+    fn listing(src: &str) -> String {
+        let script = Interpreter::new()
+            .with_stderr(CapturedOutput::new())
+            .compile_script(src)
+            .expect("the program compiles");
+        disassemble(&script)
+    }
+
+    #[test]
+    fn opcode_names() {
+        assert_eq!(op_name(OpCode::Constant), "OP_CONSTANT");
+        assert_eq!(op_name(OpCode::GetLocal), "OP_GET_LOCAL");
+        assert_eq!(op_name(OpCode::JumpIfFalse), "OP_JUMP_IF_FALSE");
+    }
+
+    #[test]
+    fn constants_and_lines() {
+        assert_eq!(
+            listing("var a = 1.5;\nprint a + \"x\";"),
+            "== <script> ==
+0000    1 OP_CONSTANT         0 '1.5'
+0003    | OP_DEFINE_GLOBAL    1 'a'
+0006    2 OP_GET_GLOBAL       1 'a'
+0009    | OP_CONSTANT         2 'x'
+0012    | OP_ADD
+0013    | OP_PRINT
+0014    | OP_NIL
+0015    | OP_RETURN
+"
+        );
+    }
+
+    #[test]
+    fn jumps_closures_and_nested_functions() {
+        assert_eq!(
+            listing("fun f(n) {\n  while (n) n = nil;\n  fun g() { return n; }\n}"),
+            "== <script> ==
+0000    1 OP_CLOSURE          0 <fn f>
+0003    | OP_DEFINE_GLOBAL    1 'f'
+0006    | OP_NIL
+0007    | OP_RETURN
+
+== f ==
+0000    2 OP_GET_LOCAL        1
+0002    | OP_JUMP_IF_FALSE    2 -> 13
+0005    | OP_POP
+0006    | OP_NIL
+0007    | OP_SET_LOCAL        1
+0009    | OP_POP
+0010    | OP_LOOP            10 -> 0
+0013    | OP_POP
+0014    3 OP_CLOSURE          0 <fn g>
+0017    |                     local 1
+0019    1 OP_NIL
+0020    | OP_RETURN
+
+== g ==
+0000    3 OP_GET_UPVALUE      0
+0002    | OP_RETURN
+0003    | OP_NIL
+0004    | OP_RETURN
+"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use super::chunk::CompiledFunction;
+use crate::{
+    gc::{Trace, Tracer},
+    literal::Literal,
+};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+/// A variable a closure has captured. It points at the variable's stack slot
+/// while the variable is in scope, and holds the value itself once the scope
+/// has ended.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Literal),
+}
+
+/// A compiled function along with the variables it captured when it was
+/// created
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<CompiledFunction>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// Classes in the VM copy their superclass's methods when they are created,
+/// so looking a method up never has to walk the superclass chain.
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub methods: RefCell<HashMap<String, Rc<Closure>>>,
+}
+
+impl Class {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            methods: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Rc<Closure>> {
+        self.methods.borrow().get(name).cloned()
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: RefCell<HashMap<String, Literal>>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+/// A method read off an instance, calling it runs the method with `this` set
+/// to `receiver`
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Literal,
+    pub method: Rc<Closure>,
+}
+
+impl Trace for RefCell<Upvalue> {
+    fn trace(&self, tracer: &mut Tracer) {
+        let Ok(upvalue) = self.try_borrow() else {
+            return tracer.busy();
+        };
+        // open upvalues point at the stack, which is a root anyway
+        if let Upvalue::Closed(value) = &*upvalue {
+            tracer.literal(value);
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut upvalue) = self.try_borrow_mut() {
+            if let Upvalue::Closed(value) = &mut *upvalue {
+                *value = Literal::Nil;
+            }
+        }
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.upvalues
+            .iter()
+            .for_each(|upvalue| tracer.object(upvalue));
+    }
+}
+
+impl Trace for Class {
+    fn trace(&self, tracer: &mut Tracer) {
+        let Ok(methods) = self.methods.try_borrow() else {
+            return tracer.busy();
+        };
+        methods.values().for_each(|method| tracer.object(method));
+    }
+
+    fn clear(&self) {
+        if let Ok(mut methods) = self.methods.try_borrow_mut() {
+            methods.clear();
+        }
+    }
+}
+
+impl Trace for Instance {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.object(&self.class);
+        let Ok(fields) = self.fields.try_borrow() else {
+            return tracer.busy();
+        };
+        fields.values().for_each(|value| tracer.literal(value));
+    }
+
+    fn clear(&self) {
+        if let Ok(mut fields) = self.fields.try_borrow_mut() {
+            fields.clear();
+        }
+    }
+}
+
+impl Trace for BoundMethod {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.literal(&self.receiver);
+        tracer.object(&self.method);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::{
+    error::RuntimeError,
+    interpreter::{Backend, CapturedOutput, InterpretError, Interpreter},
+    literal::Literal,
+};
+
+/// Runs `src` on one backend, returning what it printed and how it failed
+fn run(src: &str, backend: Backend) -> (String, Result<(), InterpretError>) {
+    let output = CapturedOutput::new();
+    let mut interpreter = Interpreter::new()
+        .with_backend(backend)
+        .with_stdout(output.clone())
+        .with_stderr(CapturedOutput::new());
+    let result = interpreter.run_source(src);
+    (output.contents(), result)
+}
+
+/// What `src` prints on the VM, which has to match the tree-walker
+fn execute(src: &str) -> String {
+    let (expected, result) = run(src, Backend::TreeWalker);
+    result.expect("the tree-walker runs the program");
+    let (output, result) = run(src, Backend::Vm);
+    result.expect("the VM runs the program");
+    assert_eq!(output, expected, "the backends disagree");
+    output
+}
+
+/// The error `src` fails with on the VM, which has to match the tree-walker
+fn runtime_error(src: &str) -> RuntimeError {
+    let errors = [Backend::TreeWalker, Backend::Vm].map(|backend| match run(src, backend) {
+        (_, Err(InterpretError::Runtime(error))) => error,
+        (_, result) => panic!("expected a runtime error on {backend:?}, got {result:?}"),
+    });
+    let [expected, error] = errors;
+    assert_eq!(error, expected, "the backends disagree");
+    error
+}
+
+#[test]
+fn arithmetic_and_logic() {
+    assert_eq!(
+        execute(
+            "print (5 + 2) * -6 == -42;
+            print 7 % 3 + 7 ~/ 2 + 2 ** 3;
+            print \"a\" + \"b\" == \"ab\";
+            print !nil and 1 or 2;
+            print nil or \"default\";
+            print 1 < 2 and 2 <= 2 and 3 > 2 and 3 >= 4;"
+        ),
+        "true\n12\ntrue\n1\ndefault\nfalse\n"
+    );
+}
+
+#[test]
+fn variables_and_scopes() {
+    assert_eq!(
+        execute(
+            "var a = \"global\";
+            {
+                var a = \"outer\";
+                {
+                    var a = \"inner\";
+                    print a;
+                }
+                print a;
+                a = \"changed\";
+                print a;
+            }
+            print a;
+            var b;
+            print b;"
+        ),
+        "inner\nouter\nchanged\nglobal\nnil\n"
+    );
+}
+
+#[test]
+fn loops() {
+    assert_eq!(
+        execute(
+            "var total = 0;
+            for (var i = 0; i < 10; i = i + 1) {
+                var skip = i % 2 == 0;
+                if (skip) continue;
+                if (i > 7) break;
+                total = total + i;
+            }
+            print total;
+            var n = 0;
+            while (true) {
+                n = n + 1;
+                { var deep = n; if (deep == 3) break; }
+            }
+            print n;"
+        ),
+        "16\n3\n"
+    );
+}
+
+#[test]
+fn closures() {
+    assert_eq!(
+        execute(
+            "fun counter() {
+                var count = 0;
+                fun increment() {
+                    count = count + 1;
+                    return count;
+                }
+                return increment;
+            }
+            var a = counter();
+            var b = counter();
+            a(); a();
+            print a();
+            print b();
+
+            // both closures share the variable, even after it leaves the stack
+            var get; var set;
+            {
+                var shared = 1;
+                fun g() { return shared; }
+                fun s(value) { shared = value; }
+                get = g; set = s;
+            }
+            set(5);
+            print get();
+
+            // captured through a function that doesn't use the variable itself
+            fun outer() {
+                var x = \"outer\";
+                fun middle() {
+                    fun inner() { return x; }
+                    return inner;
+                }
+                return middle()();
+            }
+            print outer();"
+        ),
+        "3\n1\n5\nouter\n"
+    );
+}
+
+#[test]
+fn closures_in_loops_capture_each_iteration() {
+    assert_eq!(
+        execute(
+            "var first; var second;
+            for (var i = 0; i < 2; i = i + 1) {
+                var j = i * 10;
+                fun f() { return j; }
+                if (i == 0) first = f; else second = f;
+                if (i == 0) continue;
+            }
+            print first();
+            print second();"
+        ),
+        "0\n10\n"
+    );
+}
+
+#[test]
+fn recursion() {
+    assert_eq!(
+        execute(
+            "fun fib(n) {
+                if (n < 2) return n;
+                return fib(n - 1) + fib(n - 2);
+            }
+            print fib(15);
+            {
+                fun even(n) { if (n == 0) return true; return odd(n - 1); }
+                fun odd(n) { if (n == 0) return false; return even(n - 1); }
+                print even;
+            }"
+        ),
+        "610\n<fn even>\n"
+    );
+}
+
+#[test]
+fn classes() {
+    assert_eq!(
+        execute(
+            "class Point {
+                init(x, y) {
+                    this.x = x;
+                    this.y = y;
+                    return;
+                }
+                sum() { return this.x + this.y; }
+                adder() {
+                    fun add(n) { return this.sum() + n; }
+                    return add;
+                }
+            }
+            var p = Point(1, 2);
+            print p.sum();
+            print p.adder()(10);
+            var sum = p.sum;
+            p.x = 5;
+            print sum();
+            print p.init(0, 0) == p;
+            print Point;
+            print p;
+            print type(Point) + \" \" + type(p) + \" \" + type(sum);"
+        ),
+        "3\n13\n7\ntrue\nPoint\nPoint instance\nclass instance function\n"
+    );
+}
+
+#[test]
+fn inheritance() {
+    assert_eq!(
+        execute(
+            "class A {
+                name() { return \"A\"; }
+                greet() { return \"hi from \" + this.name(); }
+            }
+            class B < A {
+                name() { return \"B\"; }
+                greet() { return super.greet() + \"!\"; }
+            }
+            class C < B {
+                greet() {
+                    fun later() { return super.greet(); }
+                    return later;
+                }
+            }
+            print B().greet();
+            print C().greet()();"
+        ),
+        "hi from B!\nhi from B!\n"
+    );
+}
+
+#[test]
+fn collections_and_strings() {
+    assert_eq!(
+        execute(
+            "var list = [1, 2, 3];
+            list[0] = \"one\";
+            print list;
+            var map = {\"a\": 1, 2: [true]};
+            map[\"b\"] = map[2][0];
+            print map;
+            print len(map) + len(list);
+            print \"${list[0]} and ${map[\"a\"] + 1}\";
+            print len(\"a,b\".split(\",\"));
+            print math.max(1, 2);"
+        ),
+        "[\"one\", 2, 3]\n{2: [true], \"a\": 1, \"b\": true}\n6\none and 2\n2\n2\n"
+    );
+}
+
+#[test]
+fn same_errors() {
+    for src in [
+        "print -\"a\";",
+        "print 1 + nil;",
+        "print 1 ~/ 0;",
+        "print undefined;",
+        "undefined = 1;",
+        "fun f(a) {} f();",
+        "class A {} A(1);",
+        "class A { init(a) {} } A();",
+        "\"not a function\"();",
+        "print 1 .x;",
+        "var n = 1; n.x = 1;",
+        "class A {} print A().missing;",
+        "print [1][1];",
+        "print {}[\"a\"];",
+        "print {nil: 1};",
+        "var NotAClass = 1; class B < NotAClass {}",
+        "print \"abc\".slice(2, 1);",
+        "fun outer() { fun inner() { return 1 + nil; } return inner(); } outer();",
+    ] {
+        let error = runtime_error(src);
+        assert!(!error.message.is_empty(), "{src}");
+    }
+}
+
+#[test]
+fn stack_overflow() {
+    let (_, result) = run("fun f() { f(); } f();", Backend::Vm);
+    let Err(InterpretError::Runtime(error)) = result else {
+        panic!("expected a stack overflow, got {result:?}");
+    };
+    assert_eq!(error.message, "Stack overflow.");
+    assert_eq!(error.code, "E0208");
+}
+
+#[test]
+fn too_many_locals() {
+    let locals: String = (0..300).map(|i| format!("var v{i} = {i};")).collect();
+    let (_, result) = run(&format!("{{ {locals} }}"), Backend::Vm);
+    let Err(InterpretError::Static(errors)) = result else {
+        panic!("expected a compile error, got {result:?}");
+    };
+    assert_eq!(errors[0].code, "E0300");
+    assert_eq!(errors[0].message, "Too many local variables in function.");
+}
+
+#[test]
+fn globals_are_shared_with_the_embedder() {
+    let mut interpreter = Interpreter::new()
+        .with_backend(Backend::Vm)
+        .with_stdout(CapturedOutput::new());
+    interpreter.set_global("start", 40.0);
+    interpreter
+        .run_source("var answer = start + 2; fun twice(n) { return n * 2; }")
+        .unwrap();
+    assert_eq!(
+        interpreter.get_global("answer"),
+        Some(Literal::Number(42.0))
+    );
+    assert_eq!(
+        interpreter.eval_expr("twice(answer)").unwrap(),
+        Literal::Number(84.0)
+    );
+}